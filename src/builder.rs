@@ -0,0 +1,135 @@
+use std::fmt;
+use crate::charclass::CharClass;
+use crate::dfa::{DFA, TooManyStates};
+use crate::helper::ast;
+use crate::helper::parser::{self, ParseError, DEFAULT_REPEAT_LIMIT};
+use crate::nfa::NFA;
+use crate::ops::Counterexample;
+
+// Either step of turning a regex into a DFA can fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    Parse(ParseError),
+    TooManyStates(TooManyStates),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Parse(err) => write!(f, "{}", err),
+            BuildError::TooManyStates(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<ParseError> for BuildError {
+    fn from(err: ParseError) -> BuildError {
+        BuildError::Parse(err)
+    }
+}
+
+impl From<TooManyStates> for BuildError {
+    fn from(err: TooManyStates) -> BuildError {
+        BuildError::TooManyStates(err)
+    }
+}
+
+// Comparing two regexes fails when either of them can't be built, or with
+// the shortest word they disagree on
+#[derive(Debug, Clone, PartialEq)]
+pub enum EquivalenceError {
+    Build(BuildError),
+    Counterexample(Counterexample),
+}
+
+impl fmt::Display for EquivalenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EquivalenceError::Build(err) => write!(f, "{}", err),
+            EquivalenceError::Counterexample(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EquivalenceError {}
+
+impl From<BuildError> for EquivalenceError {
+    fn from(err: BuildError) -> EquivalenceError {
+        EquivalenceError::Build(err)
+    }
+}
+
+impl From<Counterexample> for EquivalenceError {
+    fn from(err: Counterexample) -> EquivalenceError {
+        EquivalenceError::Counterexample(err)
+    }
+}
+
+// Builds automata out of regexes with limits on how large they may get, e.g.
+//   Builder::new().state_limit(10_000).build_dfa("(a|b)*a(a|b){20}")
+// fails instead of spending its time and memory on two million DFA states.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    repeat_limit: usize,
+    state_limit: Option<usize>,
+    complete_over: Option<CharClass>,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            repeat_limit: DEFAULT_REPEAT_LIMIT,
+            state_limit: None,
+            complete_over: None,
+        }
+    }
+
+    // How many NFA states a single `{n,m}` may expand to, `DEFAULT_REPEAT_LIMIT` unless set
+    pub fn repeat_limit(mut self, limit: usize) -> Builder {
+        self.repeat_limit = limit;
+        self
+    }
+
+    // How many states the subset construction may discover, unlimited unless set
+    pub fn state_limit(mut self, limit: usize) -> Builder {
+        self.state_limit = Some(limit);
+        self
+    }
+
+    // Build DFAs that are complete over `alphabet` instead of partial ones
+    pub fn complete_over(mut self, alphabet: CharClass) -> Builder {
+        self.complete_over = Some(alphabet);
+        self
+    }
+
+    pub fn build_nfa(&self, regex: &str) -> Result<NFA, ParseError> {
+        parser::build_nfa_with_limit(&ast::parse(regex)?, self.repeat_limit)
+    }
+
+    pub fn build_dfa(&self, regex: &str) -> Result<DFA, BuildError> {
+        let nfa: NFA = self.build_nfa(regex)?;
+        let max_states: usize = self.state_limit.unwrap_or(usize::MAX);
+        let dfa: DFA = match &self.complete_over {
+            Some(alphabet) => nfa.to_complete_dfa_with_limit(alphabet, max_states)?,
+            None => nfa.to_dfa_with_limit(max_states)?,
+        };
+        Ok(dfa)
+    }
+
+    // Whether `re1` and `re2` accept the same words, with both built under the
+    // same limits. A counterexample says `first` for a word only `re1` accepts.
+    pub fn equivalent(&self, re1: &str, re2: &str) -> Result<(), EquivalenceError> {
+        let first: DFA = self.build_dfa(re1)?;
+        let second: DFA = self.build_dfa(re2)?;
+        first.equivalent(&second)?;
+        Ok(())
+    }
+}
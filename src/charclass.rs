@@ -0,0 +1,159 @@
+use std::fmt;
+
+// A set of characters stored as sorted, non-overlapping, non-adjacent inclusive ranges
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+// The next and previous Unicode scalar values, skipping over the surrogate block
+pub fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+pub fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        _ => char::from_u32(c as u32 - 1),
+    }
+}
+
+impl CharClass {
+    pub fn new(mut ranges: Vec<(char, char)>) -> CharClass {
+        ranges.retain(|&(start, end)| start <= end);
+        ranges.sort();
+
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end || next_char(*last_end) == Some(start) => {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+
+        CharClass { ranges: merged }
+    }
+
+    pub fn from_char(c: char) -> CharClass {
+        CharClass { ranges: vec![(c, c)] }
+    }
+
+    // \d
+    pub fn digit() -> CharClass {
+        CharClass::new(vec![('0', '9')])
+    }
+
+    // \w
+    pub fn word() -> CharClass {
+        CharClass::new(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+    }
+
+    // \s
+    pub fn space() -> CharClass {
+        CharClass::new(vec![('\t', '\r'), (' ', ' ')])
+    }
+
+    // `.` matches anything except a newline
+    pub fn dot() -> CharClass {
+        CharClass::from_char('\n').negate()
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(start, end)| {
+                if end < c {
+                    std::cmp::Ordering::Less
+                } else if start > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    // How many characters are in the class. Surrogates aren't characters,
+    // so a range across them doesn't count them.
+    pub fn char_count(&self) -> u128 {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| {
+                let surrogates: u32 = if start <= '\u{D7FF}' && end >= '\u{E000}' { 0x800 } else { 0 };
+                (end as u32 - start as u32 + 1 - surrogates) as u128
+            })
+            .sum()
+    }
+
+    // Every character that is not in the class
+    pub fn negate(&self) -> CharClass {
+        let mut ranges: Vec<(char, char)> = Vec::new();
+        let mut start: Option<char> = Some('\0');
+
+        for &(lo, hi) in &self.ranges {
+            if let Some(s) = start {
+                if let Some(before) = prev_char(lo) {
+                    if s <= before {
+                        ranges.push((s, before));
+                    }
+                }
+            }
+            start = next_char(hi);
+        }
+
+        if let Some(s) = start {
+            ranges.push((s, char::MAX));
+        }
+
+        CharClass { ranges }
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\t' => "\\t".to_string(),
+        '\r' => "\\r".to_string(),
+        '\\' | ']' | '-' | '^' => format!("\\{}", c),
+        _ if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        _ => c.to_string(),
+    }
+}
+
+// Prints the class the way it would be written in a regex, using the negated
+// form when that is shorter, e.g. `a`, `[a-z_]` or `[^\n]`
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let [(start, end)] = self.ranges[..] {
+            if start == end && !matches!(start, ']' | '-' | '^') {
+                return write!(f, "{}", escape(start));
+            }
+        }
+
+        let negated = self.negate();
+        let (prefix, ranges) = if negated.ranges.len() < self.ranges.len() {
+            ("^", &negated.ranges)
+        } else {
+            ("", &self.ranges)
+        };
+
+        write!(f, "[{}", prefix)?;
+        for &(start, end) in ranges {
+            if start == end {
+                write!(f, "{}", escape(start))?;
+            } else {
+                write!(f, "{}-{}", escape(start), escape(end))?;
+            }
+        }
+        write!(f, "]")
+    }
+}
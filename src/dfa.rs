@@ -1,125 +1,822 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use crate::nfa::NFA;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Range;
+use crate::charclass::{CharClass, next_char, prev_char};
+use crate::nfa::{NFA, Look, Symbol, is_word_char};
 
+// The characters a DFA reads, grouped into classes that no transition ever
+// tells apart. Transitions are stored per class, so `[a-z]` costs one column
+// instead of 26 and `[ac]` puts 'a' and 'c' in the same one.
+#[derive(Debug, Clone)]
+pub struct Alphabet {
+    // The characters are split into intervals. Each interval is named after
+    // its first character and runs up to the next one, so `['\0', 'a', '{']`
+    // stands for `[\0-`]`, `[a-z]` and `[{-\u{10FFFF}]`.
+    pub sigma: Vec<char>,
+    // classes[i] is the class of interval sigma[i]
+    pub classes: Vec<usize>,
+    // The first character of every class
+    pub representatives: Vec<char>,
+}
+
+impl Alphabet {
+    // Splits the characters at every boundary of a label, so that no label
+    // ever contains only part of an interval, then puts intervals that are in
+    // exactly the same labels into the same class. This way `.` or `[^abc]`
+    // cost a couple of classes instead of listing every character.
+    pub fn new(labels: &[&CharClass]) -> Alphabet {
+        let mut sigma: Vec<char> = vec!['\0'];
+        for class in labels {
+            for &(start, end) in class.ranges() {
+                sigma.push(start);
+                sigma.extend(next_char(end));
+            }
+        }
+        sigma.sort();
+        sigma.dedup();
+
+        Alphabet::from_signatures(sigma, |c| labels.iter().map(|label| label.contains(c)).collect::<Vec<bool>>())
+    }
+
+    // Numbers the classes of the intervals starting at `sigma` in the order of
+    // their first interval, putting intervals with the same signature together
+    fn from_signatures<S: Eq + Hash>(sigma: Vec<char>, signature: impl Fn(char) -> S) -> Alphabet {
+        let mut ids: HashMap<S, usize> = HashMap::new();
+        let mut representatives: Vec<char> = Vec::new();
+        let classes: Vec<usize> = sigma
+            .iter()
+            .map(|&start| {
+                *ids.entry(signature(start)).or_insert_with(|| {
+                    representatives.push(start);
+                    representatives.len() - 1
+                })
+            })
+            .collect();
+
+        Alphabet { sigma, classes, representatives }
+    }
+
+    // The coarsest alphabet that splits both `self` and `other`, along with
+    // the class of `self` and of `other` each of its classes falls into
+    pub fn product(&self, other: &Alphabet) -> (Alphabet, Vec<(usize, usize)>) {
+        let mut sigma: Vec<char> = self.sigma.iter().chain(&other.sigma).copied().collect();
+        sigma.sort();
+        sigma.dedup();
+
+        let alphabet: Alphabet = Alphabet::from_signatures(sigma, |c| (self.class_of(c), other.class_of(c)));
+        let pairs: Vec<(usize, usize)> = alphabet.representatives
+            .iter()
+            .map(|&c| (self.class_of(c), other.class_of(c)))
+            .collect();
+        (alphabet, pairs)
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.representatives.len()
+    }
+
+    // The class `c` belongs to
+    pub fn class_of(&self, c: char) -> usize {
+        self.classes[self.sigma.partition_point(|&start| start <= c) - 1]
+    }
+
+    // All the characters of class `index`
+    pub fn class(&self, index: usize) -> CharClass {
+        let ranges: Vec<(char, char)> = (0..self.sigma.len())
+            .filter(|&i| self.classes[i] == index)
+            .map(|i| {
+                let end: char = match self.sigma.get(i + 1) {
+                    Some(&next) => prev_char(next).unwrap(),
+                    None => char::MAX,
+                };
+                (self.sigma[i], end)
+            })
+            .collect();
+        CharClass::new(ranges)
+    }
+}
+
+// The sink state every DFA has at id 0. It is never final and never left, and
+// stands for every transition the DFA doesn't have: in a partial DFA all the
+// ones no match can continue through, in a complete one those on characters
+// outside its alphabet. Matching stops as soon as it is reached.
+pub const DEAD: usize = 0;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub struct DFA {
-    pub sigma: Vec<char>,
-    pub states: Vec<Vec<String>>,
-    pub q0: Vec<String>,
-    pub final_states: Vec<Vec<String>>,
-    pub transitions: HashMap<Vec<String>, HashMap<char, Vec<String>>>,
+    pub alphabet: Alphabet,
+    // For a complete DFA, the characters every state has a transition on that
+    // doesn't lead to DEAD. A partial DFA is `None` and sends a character to
+    // DEAD whenever no match can continue after it.
+    pub complete_over: Option<CharClass>,
+    pub q0: usize,
+    // Where a search starts, depending on the character before the start
+    // position, indexed by `Context`. The `Context::Start` entry is q0.
+    pub start_states: [usize; 3],
+    // Whether the input is accepted when it ends in the state
+    pub final_states: Vec<bool>,
+    // matches_before[q][i] tells whether a match ends in state q when the next
+    // character is in class i. It only differs from final_states[q] when `$`,
+    // `\b` or `\B` look past the end of the match.
+    pub matches_before: Vec<Vec<bool>>,
+    // transitions[q][i] is the state q goes to on any character of class i
+    pub transitions: Vec<Vec<usize>>,
+    // The NFA states each DFA state was built from. Only used to label the graph.
+    pub labels: Option<Vec<Vec<usize>>>,
+}
+
+// What a DFA state remembers about the character before the current position,
+// which is all `^`, `\b` and `\B` need to know about the past
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Context {
+    Start,
+    NonWord,
+    Word,
+}
+
+impl Context {
+    pub(crate) fn after(c: char) -> Context {
+        if is_word_char(c) { Context::Word } else { Context::NonWord }
+    }
+
+    // The context at byte offset `pos` of `haystack`
+    pub(crate) fn at(haystack: &str, pos: usize) -> Context {
+        haystack[..pos].chars().next_back().map_or(Context::Start, Context::after)
+    }
+
+    // The previous character in the terms of `Look::holds`
+    fn prev(self) -> Option<bool> {
+        match self {
+            Context::Start => None,
+            Context::NonWord => Some(false),
+            Context::Word => Some(true),
+        }
+    }
+}
+
+// Which contexts the assertions of an NFA can tell apart
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Contexts {
+    starts: bool,
+    words: bool,
+}
+
+impl Contexts {
+    pub(crate) fn of(nfa: &NFA) -> Contexts {
+        Contexts {
+            starts: nfa.uses_look(Look::Start),
+            words: nfa.uses_look(Look::WordBoundary) || nfa.uses_look(Look::NotWordBoundary),
+        }
+    }
+
+    // Contexts no assertion can tell apart are merged, so a regex without
+    // assertions gets the same states as a plain subset construction
+    pub(crate) fn merge(self, context: Context) -> Context {
+        match context {
+            Context::Start if self.starts => Context::Start,
+            Context::Word if self.words => Context::Word,
+            _ => Context::NonWord,
+        }
+    }
+}
+
+// The subset construction found more states than it was allowed to build
+#[derive(Debug, Clone, PartialEq)]
+pub struct TooManyStates {
+    pub limit: usize,
+    // How many states besides DEAD had been discovered when the construction
+    // gave up, which is more than `limit` by however many the last state
+    // expanded led to
+    pub states: usize,
+}
+
+impl fmt::Display for TooManyStates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DFA would need more than {} states, gave up after reaching {}", self.limit, self.states)
+    }
+}
+
+impl std::error::Error for TooManyStates {}
+
+// The subsets of NFA states found so far by the subset construction
+struct Subsets {
+    ids: HashMap<(Vec<usize>, Context), usize>,
+    labels: Vec<Vec<usize>>,
+    contexts: Vec<Context>,
+    queue: VecDeque<usize>,
+}
+
+impl Subsets {
+    // Every subset gets the next free id when it is first discovered
+    fn id(&mut self, states: Vec<usize>, context: Context) -> usize {
+        if let Some(&id) = self.ids.get(&(states.clone(), context)) {
+            return id;
+        }
+        let id: usize = self.labels.len();
+        self.ids.insert((states.clone(), context), id);
+        self.labels.push(states);
+        self.contexts.push(context);
+        self.queue.push_back(id);
+        id
+    }
 }
 
 impl NFA {
-    fn epsilon_closure(&self, states: &HashSet<String>) -> HashSet<String> {
-        let mut closure: HashSet<String> = states.clone();
-        let mut stack: Vec<String> = states.iter().cloned().collect();
+    // Extends `states` with every state reachable through ε-moves, keeping it
+    // sorted. Assertions are only crossed where `holds` says they hold.
+    fn epsilon_closure(&self, states: &mut Vec<usize>, holds: impl Fn(Look) -> bool) {
+        let mut seen: Vec<bool> = vec![false; self.state_count()];
+        let mut stack: Vec<usize> = states.clone();
+        for &state in states.iter() {
+            seen[state] = true;
+        }
 
         while let Some(state) = stack.pop() {
-            if let Some(transitions) = self.transitions.get(&state) {
-                if let Some(next_states) = transitions.get(&'\0') {
-                    for next_state in next_states {
-                        if closure.insert(next_state.clone()) {
-                            stack.push(next_state.clone());
-                        }
-                    }
+            for (symbol, next_state) in &self.transitions[state] {
+                let crossable: bool = match symbol {
+                    Symbol::Look(look) => holds(*look),
+                    _ => symbol.is_epsilon(),
+                };
+                if crossable && !seen[*next_state] {
+                    seen[*next_state] = true;
+                    states.push(*next_state);
+                    stack.push(*next_state);
                 }
             }
         }
 
-        closure
+        states.sort();
     }
 
-    fn move_via_symbol(&self, states: &HashSet<String>, symbol: char) -> HashSet<String> {
-        let mut next_states: HashSet<String> = HashSet::new();
+    fn move_via_symbol(&self, states: &[usize], symbol: char) -> Vec<usize> {
+        let mut next_states: Vec<usize> = Vec::new();
 
-        for state in states {
-            if let Some(transitions) = self.transitions.get(state) {
-                if let Some(target_states) = transitions.get(&symbol) {
-                    for target_state in target_states {
-                        next_states.insert(target_state.clone());
+        for &state in states {
+            for (label, target_state) in &self.transitions[state] {
+                if let Symbol::Class(class) = label {
+                    if class.contains(symbol) {
+                        next_states.push(*target_state);
                     }
                 }
             }
         }
 
+        next_states.sort();
+        next_states.dedup();
+        next_states
+    }
+
+    // The alphabet of the DFA: the intervals and classes of the transition
+    // labels. With `\b` or `\B` every class is also made either all word
+    // characters or none, and with a `domain` either all inside it or none.
+    pub(crate) fn alphabet(&self, contexts: Contexts, domain: Option<&CharClass>) -> Alphabet {
+        let word: CharClass = CharClass::word();
+        let labels: Vec<&CharClass> = self.sigma
+            .iter()
+            .chain(contexts.words.then_some(&word))
+            .chain(domain)
+            .collect();
+        Alphabet::new(&labels)
+    }
+
+    // The subset q0 starts in, before any assertion is resolved
+    pub(crate) fn start_subset(&self) -> Vec<usize> {
+        let mut states: Vec<usize> = vec![self.q0];
+        self.epsilon_closure(&mut states, |_| false);
+        states
+    }
+
+    // Extends a subset with what its assertions allow once the characters
+    // around the position are known. `next` tells whether the next character
+    // is a word character and is `None` at the end of the haystack.
+    pub(crate) fn resolve(&self, states: &[usize], prev: Context, next: Option<bool>) -> Vec<usize> {
+        let mut states: Vec<usize> = states.to_vec();
+        self.epsilon_closure(&mut states, |look| look.holds(prev.prev(), next));
+        states
+    }
+
+    // The subset reached from a resolved subset on `symbol`
+    pub(crate) fn step(&self, states: &[usize], symbol: char) -> Vec<usize> {
+        let mut next_states: Vec<usize> = self.move_via_symbol(states, symbol);
+        self.epsilon_closure(&mut next_states, |_| false);
         next_states
     }
 
-    fn convert_set_to_state(&self, states: &HashSet<String>) -> Vec<String> {
-        let mut state_vec: Vec<String> = states.iter().cloned().collect();
-        state_vec.sort();
-        state_vec
+    pub(crate) fn contains_final(&self, states: &[usize]) -> bool {
+        states.iter().any(|s| self.final_states.contains(s))
     }
 
     pub fn to_dfa(&self) -> DFA {
-        let sigma: Vec<char> = self.sigma.iter().cloned()
-            .filter(|&c| c != '\0')
-            .collect();
+        self.to_dfa_with_limit(usize::MAX)
+            .expect("no DFA can have more than usize::MAX states")
+    }
+
+    // Builds a partial DFA, where DEAD takes the place of the empty subset.
+    //
+    // The number of subsets can grow exponentially with the size of the NFA,
+    // e.g. for `(a|b)*a(a|b){20}`, so the construction stops with an error
+    // once it has discovered more than `max_states` of them, not counting DEAD.
+    // It only checks after expanding a whole state.
+    pub fn to_dfa_with_limit(&self, max_states: usize) -> Result<DFA, TooManyStates> {
+        self.subset_construction(None, max_states)
+    }
 
-        let mut init: HashSet<String> = HashSet::new();
-        init.insert(self.q0.clone());
+    // Builds a DFA that is complete over `alphabet`: the empty subset is a
+    // state of its own that every character of `alphabet` loops on, and only
+    // the characters outside of `alphabet` go to DEAD.
+    pub fn to_complete_dfa_with_limit(&self, alphabet: &CharClass, max_states: usize) -> Result<DFA, TooManyStates> {
+        self.subset_construction(Some(alphabet), max_states)
+    }
+
+    // Subset construction. When the NFA has assertions, a DFA state also keeps
+    // the context of the previous character, and the assertions of its subset
+    // are only resolved on the way out, once the next character is known.
+    fn subset_construction(&self, domain: Option<&CharClass>, max_states: usize) -> Result<DFA, TooManyStates> {
+        let contexts: Contexts = Contexts::of(self);
+        let alphabet: Alphabet = self.alphabet(contexts, domain);
+        let start_state: Vec<usize> = self.start_subset();
+
+        // DEAD is set up by hand, as no subset maps to it through `ids`
+        let mut subsets = Subsets {
+            ids: HashMap::new(),
+            labels: vec![Vec::new()],
+            contexts: vec![Context::NonWord],
+            queue: VecDeque::new(),
+        };
+        let start_states: [usize; 3] = [Context::Start, Context::NonWord, Context::Word]
+            .map(|context| subsets.id(start_state.clone(), contexts.merge(context)));
+        // DEAD has no subset of its own and doesn't count against the limit
+        let check = |subsets: &Subsets| match subsets.labels.len() - 1 {
+            states if states > max_states => Err(TooManyStates { limit: max_states, states }),
+            _ => Ok(()),
+        };
+        check(&subsets)?;
+
+        let mut final_states: Vec<bool> = vec![false];
+        let mut matches_before: Vec<Vec<bool>> = vec![vec![false; alphabet.class_count()]];
+        let mut transitions: Vec<Vec<usize>> = vec![vec![DEAD; alphabet.class_count()]];
+
+        while let Some(current_id) = subsets.queue.pop_front() {
+            let (subset, prev) = (&subsets.labels[current_id], subsets.contexts[current_id]);
+            let at_end: Vec<usize> = self.resolve(subset, prev, None);
+            let before: [Vec<usize>; 2] = [self.resolve(subset, prev, Some(false)), self.resolve(subset, prev, Some(true))];
+
+            let mut state_matches: Vec<bool> = Vec::with_capacity(alphabet.class_count());
+            let mut state_transitions: Vec<usize> = Vec::with_capacity(alphabet.class_count());
+
+            for &symbol in &alphabet.representatives {
+                let current_state: &[usize] = &before[is_word_char(symbol) as usize];
+                state_matches.push(self.contains_final(current_state));
+
+                let next_state: Vec<usize> = self.step(current_state, symbol);
+                let next_id: usize = match domain {
+                    Some(domain) if !domain.contains(symbol) => DEAD,
+                    None if next_state.is_empty() => DEAD,
+                    _ => subsets.id(next_state, contexts.merge(Context::after(symbol))),
+                };
+                state_transitions.push(next_id);
+            }
+            check(&subsets)?;
+
+            // States leave the queue in the order their ids were handed out
+            final_states.push(self.contains_final(&at_end));
+            matches_before.push(state_matches);
+            transitions.push(state_transitions);
+        }
+
+        Ok(DFA {
+            alphabet,
+            complete_over: domain.cloned(),
+            q0: start_states[Context::Start as usize],
+            start_states,
+            final_states,
+            matches_before,
+            transitions,
+            labels: Some(subsets.labels),
+        })
+    }
+}
+
+impl DFA {
+    pub fn state_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    // Index of the class of the alphabet that contains `c`
+    pub fn symbol_index(&self, c: char) -> usize {
+        self.alphabet.class_of(c)
+    }
 
-        let start_closure: HashSet<String> = self.epsilon_closure(&init);
-        let start_state: Vec<String> = self.convert_set_to_state(&start_closure);
+    pub fn accepts_word(&self, word: &str) -> bool {
+        let mut current_state: usize = self.q0;
+        for c in word.chars() {
+            current_state = self.transitions[current_state][self.symbol_index(c)];
+            if current_state == DEAD {
+                return false;
+            }
+        }
 
-        let mut states: Vec<Vec<String>> = Vec::new();
-        let mut transitions: HashMap<Vec<String>, HashMap<char, Vec<String>>> = HashMap::new();
+        self.final_states[current_state]
+    }
+}
 
-        let mut queue: VecDeque<Vec<String>> = VecDeque::new();
-        queue.push_back(start_state.clone());
+impl DFA {
+    // States that can reach a final state. Every other state is dead: once
+    // the DFA enters it, the word can no longer be accepted.
+    pub fn live_states(&self) -> Vec<bool> {
+        let mut reverse: Vec<Vec<usize>> = vec![Vec::new(); self.state_count()];
+        for (from, targets) in self.transitions.iter().enumerate() {
+            for &to in targets {
+                reverse[to].push(from);
+            }
+        }
 
-        while let Some(current_state) = queue.pop_front() {
-            if !states.contains(&current_state) {
-                states.push(current_state.clone());
+        let mut live: Vec<bool> = (0..self.state_count())
+            .map(|q| self.final_states[q] || self.matches_before[q].contains(&true))
+            .collect();
+        let mut stack: Vec<usize> = (0..self.state_count()).filter(|&q| live[q]).collect();
+        while let Some(state) = stack.pop() {
+            for &from in &reverse[state] {
+                if !live[from] {
+                    live[from] = true;
+                    stack.push(from);
+                }
             }
+        }
 
-            let current_set: HashSet<String> = current_state.iter().cloned().collect();
-            let mut state_transitions: HashMap<char, Vec<String>> = HashMap::new();
+        live
+    }
 
-            for &symbol in &sigma {
-                let next_set: HashSet<String> = self.move_via_symbol(&current_set, symbol);
-                let next_closure: HashSet<String> = self.epsilon_closure(&next_set);
-                let next_state: Vec<String> = self.convert_set_to_state(&next_closure);
+    fn reachable_states(&self) -> Vec<bool> {
+        let mut reachable: Vec<bool> = vec![false; self.state_count()];
+        let mut stack: Vec<usize> = vec![DEAD];
+        stack.extend(self.start_states);
+        for &state in &stack {
+            reachable[state] = true;
+        }
 
-                if !states.contains(&next_state) && !queue.contains(&next_state) {
-                    queue.push_back(next_state.clone());
+        while let Some(state) = stack.pop() {
+            for &to in &self.transitions[state] {
+                if !reachable[to] {
+                    reachable[to] = true;
+                    stack.push(to);
                 }
+            }
+        }
+
+        reachable
+    }
 
-                state_transitions.insert(symbol, next_state.clone());
+    // Hopcroft's partition refinement. Starts from {final, non-final} and keeps
+    // splitting blocks whose states disagree on which block a symbol leads to,
+    // always queueing the smaller half as the next splitter. States of the
+    // result are renumbered in BFS order from DEAD, q0 and the other start
+    // states. A partial DFA folds every state that can't reach a match into
+    // DEAD, while a complete one keeps DEAD apart from the states it still
+    // has transitions from.
+    pub fn minimize(&self) -> DFA {
+        let reachable: Vec<bool> = self.reachable_states();
+        let states: Vec<usize> = (0..self.state_count()).filter(|&q| reachable[q]).collect();
+
+        // inverse[i][q] lists the states that go to q on class i
+        let mut inverse: Vec<Vec<Vec<usize>>> = vec![vec![Vec::new(); self.state_count()]; self.alphabet.class_count()];
+        for &from in &states {
+            for (index, &to) in self.transitions[from].iter().enumerate() {
+                inverse[index][to].push(from);
             }
+        }
 
-            transitions.insert(current_state.clone(), state_transitions);
+        // States that end a match in different places can never be merged,
+        // which without assertions is the same as splitting final from non-final
+        let mut signatures: BTreeMap<(bool, bool, &[bool]), Vec<usize>> = BTreeMap::new();
+        for &q in &states {
+            let kept_apart: bool = q == DEAD && self.complete_over.is_some();
+            signatures.entry((kept_apart, self.final_states[q], &self.matches_before[q])).or_default().push(q);
         }
+        let mut blocks: Vec<Vec<usize>> = signatures.into_values().collect();
 
-        let mut final_states: Vec<Vec<String>> = Vec::new();
-        for state in &states {
-            if state.iter().any(|s| self.final_states.contains(s)) {
-                final_states.push(state.clone());
+        let mut block_of: Vec<usize> = vec![usize::MAX; self.state_count()];
+        for (id, block) in blocks.iter().enumerate() {
+            for &q in block {
+                block_of[q] = id;
             }
         }
 
+        let mut worklist: Vec<usize> = (0..blocks.len()).collect();
+
+        while let Some(splitter) = worklist.pop() {
+            let splitter_states: Vec<usize> = blocks[splitter].clone();
+
+            for inverse_on_symbol in &inverse {
+                // Every state with a transition into the splitter on this symbol, grouped by block
+                let mut touched: HashMap<usize, Vec<usize>> = HashMap::new();
+                for &to in &splitter_states {
+                    for &from in &inverse_on_symbol[to] {
+                        touched.entry(block_of[from]).or_default().push(from);
+                    }
+                }
+
+                for (block, mut inside) in touched {
+                    inside.sort();
+                    inside.dedup();
+                    if inside.len() == blocks[block].len() {
+                        continue;
+                    }
+
+                    let outside: Vec<usize> = blocks[block]
+                        .iter()
+                        .copied()
+                        .filter(|q| inside.binary_search(q).is_err())
+                        .collect();
+
+                    // The block keeps the larger half, the smaller one becomes a new block
+                    let (kept, split) = if inside.len() <= outside.len() { (outside, inside) } else { (inside, outside) };
+                    let new_block: usize = blocks.len();
+                    for &q in &split {
+                        block_of[q] = new_block;
+                    }
+                    blocks[block] = kept;
+                    blocks.push(split);
+
+                    // Only the smaller half is queued. A block still in the worklist
+                    // keeps its id, so its larger half gets a turn as well, and one
+                    // that already had its turn only needs the smaller half
+                    worklist.push(new_block);
+                }
+            }
+        }
+
+        // Number the blocks in the order a BFS from the start states reaches them
+        let mut new_id: Vec<usize> = vec![usize::MAX; blocks.len()];
+        let mut order: Vec<usize> = Vec::new();
+        let mut next: usize = 0;
+
+        for &start in std::iter::once(&DEAD).chain(&self.start_states) {
+            if new_id[block_of[start]] == usize::MAX {
+                new_id[block_of[start]] = order.len();
+                order.push(block_of[start]);
+            }
+
+            while next < order.len() {
+                let representative: usize = blocks[order[next]][0];
+                for &to in &self.transitions[representative] {
+                    let block: usize = block_of[to];
+                    if new_id[block] == usize::MAX {
+                        new_id[block] = order.len();
+                        order.push(block);
+                    }
+                }
+                next += 1;
+            }
+        }
+
+        let transitions: Vec<Vec<usize>> = order
+            .iter()
+            .map(|&block| {
+                self.transitions[blocks[block][0]]
+                    .iter()
+                    .map(|&to| new_id[block_of[to]])
+                    .collect()
+            })
+            .collect();
+
+        let final_states: Vec<bool> = order
+            .iter()
+            .map(|&block| self.final_states[blocks[block][0]])
+            .collect();
+
+        let matches_before: Vec<Vec<bool>> = order
+            .iter()
+            .map(|&block| self.matches_before[blocks[block][0]].clone())
+            .collect();
+
         DFA {
-            sigma,
-            states,
-            q0: start_state,
+            alphabet: self.alphabet.clone(),
+            complete_over: self.complete_over.clone(),
+            q0: new_id[block_of[self.q0]],
+            start_states: self.start_states.map(|start| new_id[block_of[start]]),
             final_states,
+            matches_before,
             transitions,
+            labels: None,
         }
     }
 }
 
+// A DFA whose states are sets of states of another DFA, built while a
+// haystack is scanned. Every set has exactly one successor on each class, so
+// a scan reads each character once, and the sets and transitions found are
+// kept for the rest of the search.
+struct SetCache {
+    ids: HashMap<Vec<usize>, usize>,
+    sets: Vec<Vec<usize>>,
+    // transitions[s][i] is the set s goes to on class i and a flag whose
+    // meaning depends on the direction of the scan, once it has been computed
+    transitions: Vec<Vec<Option<(usize, bool)>>>,
+}
+
+impl SetCache {
+    fn new() -> SetCache {
+        SetCache {
+            ids: HashMap::new(),
+            sets: Vec::new(),
+            transitions: Vec::new(),
+        }
+    }
+
+    fn id(&mut self, set: Vec<usize>, class_count: usize) -> usize {
+        if let Some(&id) = self.ids.get(&set) {
+            return id;
+        }
+        let id: usize = self.sets.len();
+        self.ids.insert(set.clone(), id);
+        self.sets.push(set);
+        self.transitions.push(vec![None; class_count]);
+        id
+    }
+
+    // The transition of set `id` on class `index`, worked out by `compute`
+    // the first time it is taken
+    fn next(&mut self, id: usize, index: usize, compute: impl FnOnce(&[usize]) -> (Vec<usize>, bool)) -> (usize, bool) {
+        if let Some(cached) = self.transitions[id][index] {
+            return cached;
+        }
+        let (set, flag) = compute(&self.sets[id]);
+        let next: (usize, bool) = (self.id(set, self.transitions[id].len()), flag);
+        self.transitions[id][index] = Some(next);
+        next
+    }
+}
+
 impl DFA {
-    pub fn accepts_word(&self, word: &str) -> bool {
-        let mut current_state: Vec<String> = self.q0.clone();
-        for symbol in word.chars() {
-            match self.transitions.get(&current_state)
-                .and_then(|map| map.get(&symbol)) {
-                None => return false,
-                Some(next_state) => current_state = next_state.clone(),
-            };
+    // The state a search starting at byte offset `pos` of `haystack` starts from
+    fn start_state(&self, haystack: &str, pos: usize) -> usize {
+        self.start_states[Context::at(haystack, pos) as usize]
+    }
+
+    // The state a search starting right after a character of class `index` starts from
+    fn start_state_after(&self, index: usize) -> usize {
+        self.start_states[Context::after(self.alphabet.representatives[index]) as usize]
+    }
+
+    // Runs the DFA over `haystack[start..]` and returns the end of the longest
+    // match starting at `start`. Stops as soon as no match can end any more.
+    // Whether a match ends before a character is only known once that
+    // character is seen, so the check comes before each transition.
+    fn longest_match_at(&self, haystack: &str, start: usize, live: &[bool]) -> Option<usize> {
+        let mut current_state: usize = self.start_state(haystack, start);
+        let mut end: Option<usize> = None;
+
+        for (offset, c) in haystack[start..].char_indices() {
+            let index: usize = self.symbol_index(c);
+            if self.matches_before[current_state][index] {
+                end = Some(start + offset);
+            }
+            current_state = self.transitions[current_state][index];
+            if !live[current_state] {
+                return end;
+            }
+        }
+
+        if self.final_states[current_state] {
+            end = Some(haystack.len());
+        }
+        end
+    }
+
+    // Calls `found` with every position from `from` on where a match starts,
+    // from right to left, in a single backward pass over the haystack.
+    //
+    // The pass runs the DFA in reverse: the set at a position holds every
+    // state from which the rest of the haystack completes a match, so a match
+    // starts there exactly when the set holds the start state for it.
+    fn match_starts(&self, haystack: &str, from: usize, mut found: impl FnMut(usize)) {
+        let class_count: usize = self.alphabet.class_count();
+        let mut cache: SetCache = SetCache::new();
+        let finals: Vec<usize> = (0..self.state_count()).filter(|&q| self.final_states[q]).collect();
+        let mut current: usize = cache.id(finals, class_count);
+        let mut pos: usize = haystack.len();
+
+        // Reading `c` backwards goes from the set after it to the set before
+        // it. Whether a match starts after `c` depends on `c` too, so that
+        // check is cached along with the transition.
+        for c in haystack[from..].chars().rev() {
+            let index: usize = self.symbol_index(c);
+            let start: usize = self.start_state_after(index);
+            let (before, starts_after) = cache.next(current, index, |after| {
+                let before: Vec<usize> = ((DEAD + 1)..self.state_count())
+                    .filter(|&q| self.matches_before[q][index] || after.binary_search(&self.transitions[q][index]).is_ok())
+                    .collect();
+                (before, after.binary_search(&start).is_ok())
+            });
+            if starts_after {
+                found(pos);
+            }
+            current = before;
+            pos -= c.len_utf8();
         }
 
-        self.final_states.iter().any(|final_state| final_state == &current_state)
+        if cache.sets[current].binary_search(&self.start_state(haystack, from)).is_ok() {
+            found(from);
+        }
+    }
+
+    // The leftmost match from `from` on, and the longest among those starting there
+    fn find_at(&self, haystack: &str, from: usize, live: &[bool]) -> Option<Range<usize>> {
+        let mut leftmost: Option<usize> = None;
+        self.match_starts(haystack, from, |start| leftmost = Some(start));
+        let start: usize = leftmost?;
+        self.longest_match_at(haystack, start, live).map(|end| start..end)
+    }
+
+    // Finds the leftmost match in `haystack` and, among those starting there,
+    // the longest one. The range is in bytes.
+    pub fn find(&self, haystack: &str) -> Option<Range<usize>> {
+        self.find_at(haystack, 0, &self.live_states())
+    }
+
+    // Iterates over the successive non-overlapping leftmost-longest matches
+    pub fn find_iter<'d, 'h>(&'d self, haystack: &'h str) -> Matches<'d, 'h> {
+        let mut starts: Vec<bool> = vec![false; haystack.len() + 1];
+        self.match_starts(haystack, 0, |start| starts[start] = true);
+        Matches {
+            dfa: self,
+            haystack,
+            live: self.live_states(),
+            starts,
+            pos: 0,
+            last_end: None,
+        }
+    }
+
+    // Whether any substring of `haystack` is accepted, in a single pass. The
+    // set at a position holds the state of every search started so far, and
+    // a new search starts at every character.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        let class_count: usize = self.alphabet.class_count();
+        let mut cache: SetCache = SetCache::new();
+        let mut current: usize = cache.id(vec![self.start_state(haystack, 0)], class_count);
+
+        for c in haystack.chars() {
+            let index: usize = self.symbol_index(c);
+            let (next, matched) = cache.next(current, index, |states| {
+                let mut next: Vec<usize> = states
+                    .iter()
+                    .map(|&q| self.transitions[q][index])
+                    .chain(std::iter::once(self.start_state_after(index)))
+                    .filter(|&q| q != DEAD)
+                    .collect();
+                next.sort();
+                next.dedup();
+                (next, states.iter().any(|&q| self.matches_before[q][index]))
+            });
+            if matched {
+                return true;
+            }
+            current = next;
+        }
+
+        cache.sets[current].iter().any(|&q| self.final_states[q])
+    }
+}
+
+pub struct Matches<'d, 'h> {
+    dfa: &'d DFA,
+    haystack: &'h str,
+    live: Vec<bool>,
+    // starts[pos] tells whether any match starts at byte offset pos
+    starts: Vec<bool>,
+    // Where the search for the next match starts
+    pos: usize,
+    // End of the last match that was yielded
+    last_end: Option<usize>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        loop {
+            // Where matches start doesn't depend on where the search for them
+            // starts, so the next match starts at the next marked position
+            let start: usize = (self.pos..self.starts.len()).find(|&pos| self.starts[pos])?;
+            let end: usize = self.dfa.longest_match_at(self.haystack, start, &self.live)?;
+            let found: Range<usize> = start..end;
+
+            // An empty match has to move the search one char forward, or it would
+            // be found again forever. Empty matches right where the previous match
+            // ended are skipped, so `a*` on "aab" gives 0..2 and 3..3 but not 2..2.
+            let width: usize = self.haystack[found.end..].chars().next().map_or(1, char::len_utf8);
+            if found.is_empty() && Some(found.end) == self.last_end {
+                self.pos = found.end + width;
+                continue;
+            }
+
+            self.pos = if found.is_empty() { found.end + width } else { found.end };
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
     }
 }
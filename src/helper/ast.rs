@@ -0,0 +1,275 @@
+use crate::charclass::CharClass;
+use crate::nfa::Look;
+use crate::helper::parser::{self, Group, ParseError, ParseErrorKind, Spanned, Token};
+
+// Byte range of the regex a node was parsed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ast {
+    // Matches only the empty word, e.g. the empty regex
+    Empty { span: Span },
+    Literal { c: char, span: Span },
+    Class { class: CharClass, span: Span },
+    // Zero-width assertion, `^`, `$`, `\b` or `\B`
+    Look { look: Look, span: Span },
+    Concat { items: Vec<Ast>, span: Span },
+    Alternation { alternatives: Vec<Ast>, span: Span },
+    // `*`, `+`, `?` and `{n,m}` all become a repetition, `max` is `None` when unbounded
+    Repeat { ast: Box<Ast>, min: usize, max: Option<usize>, span: Span },
+    // Capturing groups are numbered from 1 in the order of their opening
+    // parentheses, `(?:...)` has no index and only groups its contents
+    Group { ast: Box<Ast>, index: Option<usize>, name: Option<String>, span: Span },
+}
+
+impl Ast {
+    pub fn span(&self) -> Span {
+        match self {
+            Ast::Empty { span }
+            | Ast::Literal { span, .. }
+            | Ast::Class { span, .. }
+            | Ast::Look { span, .. }
+            | Ast::Concat { span, .. }
+            | Ast::Alternation { span, .. }
+            | Ast::Repeat { span, .. }
+            | Ast::Group { span, .. } => *span,
+        }
+    }
+}
+
+fn join(first: Span, last: Span) -> Span {
+    Span { start: first.start, end: last.end }
+}
+
+// The repetition bounds of a quantifier token
+fn bounds(token: &Token) -> Option<(usize, Option<usize>)> {
+    match token {
+        Token::Star => Some((0, None)),
+        Token::Plus => Some((1, None)),
+        Token::Question => Some((0, Some(1))),
+        Token::Repeat { min, max } => Some((*min, *max)),
+        _ => None,
+    }
+}
+
+// Recursive-descent parser over the token stream, following the grammar
+//   alternation := concat ('|' concat)*
+//   concat      := repeat*
+//   repeat      := atom quantifier*
+//   atom        := char | class | look | group alternation ')'
+//   group       := '(' | '(?:' | '(?<' name '>' | '(?P<' name '>'
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    // Offset of the end of the regex, where an empty trailing node is placed
+    end: usize,
+    // Number of capturing groups opened so far
+    groups: usize,
+    // The `(` of every group being parsed, outermost first
+    open: Vec<Spanned>,
+    // The error the tokenizer stopped at, if it stopped before the end
+    truncated: Option<ParseError>,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Option<&Spanned> {
+        // Concatenation is implied by the grammar, so the explicit tokens are skipped
+        while self.tokens.get(self.pos).is_some_and(|spanned| spanned.token == Token::Concat) {
+            self.pos += 1;
+        }
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Spanned> {
+        let spanned = self.peek().cloned();
+        self.pos += 1;
+        spanned
+    }
+
+    fn offset(&mut self) -> usize {
+        let end = self.end;
+        self.peek().map_or(end, |spanned| spanned.offset)
+    }
+
+    // An error that shows because the tokens ran out. When the tokenizer
+    // stopped early, its own error comes first.
+    fn at_end(&mut self, kind: ParseErrorKind, spanned: Spanned) -> ParseError {
+        self.truncated.take().unwrap_or_else(|| ParseError::new(kind, spanned))
+    }
+
+    fn next_group(&mut self) -> usize {
+        self.groups += 1;
+        self.groups
+    }
+
+    fn parse_alternation(&mut self) -> Result<Ast, ParseError> {
+        let first = self.parse_concat()?;
+        if self.peek().is_none_or(|spanned| spanned.token != Token::Union) {
+            return Ok(first);
+        }
+
+        let mut alternatives: Vec<Ast> = vec![first];
+        while let Some(bar) = self.peek().filter(|spanned| spanned.token == Token::Union).cloned() {
+            if let Some(Ast::Empty { .. }) = alternatives.last() {
+                return Err(ParseError::new(ParseErrorKind::EmptyAlternative, bar));
+            }
+            self.next();
+
+            let alternative = self.parse_concat()?;
+            if let Ast::Empty { .. } = alternative {
+                // At the top level, a `)` that follows is reported as unopened instead
+                let in_group = !self.open.is_empty();
+                match self.peek() {
+                    None => return Err(self.at_end(ParseErrorKind::TrailingUnion, bar)),
+                    Some(spanned) if spanned.token == Token::RightParen && in_group => {
+                        return Err(ParseError::new(ParseErrorKind::TrailingUnion, bar));
+                    }
+                    _ => {}
+                }
+            }
+            alternatives.push(alternative);
+        }
+
+        let span = join(alternatives[0].span(), alternatives[alternatives.len() - 1].span());
+        Ok(Ast::Alternation { alternatives, span })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast, ParseError> {
+        let mut items: Vec<Ast> = Vec::new();
+        while self.peek().is_some_and(|spanned| !matches!(spanned.token, Token::Union | Token::RightParen)) {
+            items.push(self.parse_repeat()?);
+        }
+
+        match items.len() {
+            0 => {
+                let offset = self.offset();
+                Ok(Ast::Empty { span: Span { start: offset, end: offset } })
+            }
+            1 => Ok(items.pop().unwrap()),
+            _ => {
+                let span = join(items[0].span(), items[items.len() - 1].span());
+                Ok(Ast::Concat { items, span })
+            }
+        }
+    }
+
+    fn parse_repeat(&mut self) -> Result<Ast, ParseError> {
+        let mut ast = self.parse_atom()?;
+
+        while let Some((min, max)) = self.peek().and_then(|spanned| bounds(&spanned.token)) {
+            let quantifier = self.next().unwrap();
+            let span = Span { start: ast.span().start, end: quantifier.end };
+            ast = Ast::Repeat { ast: Box::new(ast), min, max, span };
+        }
+
+        Ok(ast)
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, ParseError> {
+        let spanned = self.next().expect("parse_concat only asks for atoms when a token is left");
+        let span = Span { start: spanned.offset, end: spanned.end };
+
+        match spanned.token {
+            Token::Char(c) => Ok(Ast::Literal { c, span }),
+            Token::Class(class) => Ok(Ast::Class { class, span }),
+            Token::Look(look) => Ok(Ast::Look { look, span }),
+            Token::LeftParen(ref group) => {
+                let (index, name) = match group {
+                    Group::Capturing => (Some(self.next_group()), None),
+                    Group::Named(name) => (Some(self.next_group()), Some(name.clone())),
+                    Group::NonCapturing => (None, None),
+                };
+                self.open.push(spanned.clone());
+                let inner = self.parse_alternation()?;
+                // `parse_alternation` only stops at a `)` or at the end of the regex.
+                // Running out reports the outermost group that is still open.
+                let Some(close) = self.next() else {
+                    let outermost: Spanned = self.open[0].clone();
+                    return Err(self.at_end(ParseErrorKind::UnclosedParen, outermost));
+                };
+                self.open.pop();
+                if let Ast::Empty { .. } = inner {
+                    return Err(ParseError::new(ParseErrorKind::EmptyGroup, spanned));
+                }
+                let span = Span { start: spanned.offset, end: close.end };
+                Ok(Ast::Group { ast: Box::new(inner), index, name, span })
+            }
+            ref token if bounds(token).is_some() => {
+                Err(ParseError::new(ParseErrorKind::DanglingQuantifier, spanned))
+            }
+            _ => unreachable!("parse_concat stops at `|` and `)`, and concatenations are skipped"),
+        }
+    }
+}
+
+pub fn parse(regex: &str) -> Result<Ast, ParseError> {
+    // Errors are reported in the order they appear in the regex, so the
+    // tokens before a token that can't be read are parsed first
+    let (tokens, truncated) = parser::tokens_before_error(regex);
+    let end: usize = truncated.as_ref().map_or(regex.len(), |err| err.offset);
+    let mut parser = Parser { tokens, pos: 0, end, groups: 0, open: Vec::new(), truncated };
+
+    let ast = parser.parse_alternation()?;
+    // Outside of any group, `parse_alternation` only stops early at a `)`
+    match parser.next() {
+        None => parser.truncated.map_or(Ok(ast), Err),
+        Some(spanned) => Err(ParseError::new(ParseErrorKind::UnopenedParen, spanned)),
+    }
+}
+
+// Compatibility layer for the shunting-yard pipeline: turns the output of
+// `parser::to_postfix` into the same tree, minus the groups that postfix
+// order has no parentheses left for
+pub fn from_postfix(tokens: Vec<Spanned>) -> Result<Ast, ParseError> {
+    let mut stack: Vec<Ast> = Vec::new();
+    let mut last: Option<Spanned> = None;
+
+    for spanned in tokens {
+        // Pops an operand for the current operator or reports which operator went without one
+        let pop = |stack: &mut Vec<Ast>| {
+            stack.pop().ok_or_else(|| ParseError::new(ParseErrorKind::MissingOperand, spanned.clone()))
+        };
+        let span = Span { start: spanned.offset, end: spanned.end };
+
+        match &spanned.token {
+            Token::Char(c) => stack.push(Ast::Literal { c: *c, span }),
+            Token::Class(class) => stack.push(Ast::Class { class: class.clone(), span }),
+            Token::Look(look) => stack.push(Ast::Look { look: *look, span }),
+            Token::Union => {
+                let ast2 = pop(&mut stack)?;
+                let ast1 = pop(&mut stack)?;
+                let span = join(ast1.span(), ast2.span());
+                stack.push(Ast::Alternation { alternatives: vec![ast1, ast2], span });
+            }
+            Token::Concat => {
+                let ast2 = pop(&mut stack)?;
+                let ast1 = pop(&mut stack)?;
+                let span = join(ast1.span(), ast2.span());
+                stack.push(Ast::Concat { items: vec![ast1, ast2], span });
+            }
+            Token::LeftParen(_) | Token::RightParen => {
+                return Err(ParseError::new(ParseErrorKind::UnexpectedToken, spanned.clone()));
+            }
+            token => {
+                let (min, max) = bounds(token).unwrap();
+                let ast = pop(&mut stack)?;
+                let span = join(ast.span(), span);
+                stack.push(Ast::Repeat { ast: Box::new(ast), min, max, span });
+            }
+        }
+
+        last = Some(spanned);
+    }
+
+    match (stack.pop(), last) {
+        (None, _) => Ok(Ast::Empty { span: Span { start: 0, end: 0 } }),
+        (Some(_), Some(last)) if !stack.is_empty() => {
+            Err(ParseError::new(ParseErrorKind::MissingOperator, last))
+        }
+        (Some(ast), _) => Ok(ast),
+    }
+}
@@ -2,36 +2,42 @@ use std::fs::File;
 use std::io::Write;
 use std::process::Command;
 use std::env::temp_dir;
-use crate::nfa::NFA;
-use crate::dfa::DFA;
+use crate::nfa::{NFA, Symbol};
+use crate::dfa::{DFA, DEAD};
+use crate::charclass::CharClass;
 use std::collections::HashMap;
 
+// Escapes a transition label so it can sit inside a quoted dot string
+fn escape_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 impl NFA {
     fn to_dot(&self) -> String {
         let mut dot = String::new();
         dot.push_str("digraph NFA {\n");
         dot.push_str("    rankdir=LR;\n");
         dot.push_str("    node [shape = point]; start;\n");
-        dot.push_str(&format!("    start -> {} ;\n", self.q0));
+        dot.push_str(&format!("    start -> q{} ;\n", self.q0));
 
-        for state in &self.states {
-            if self.final_states.contains(state) {
-                dot.push_str(&format!("\t{} [shape = doublecircle];\n", state));
+        for state in 0..self.state_count() {
+            if self.final_states.contains(&state) {
+                dot.push_str(&format!("\tq{} [shape = doublecircle];\n", state));
             } else {
-                dot.push_str(&format!("\t{} [shape = circle];\n", state));
+                dot.push_str(&format!("\tq{} [shape = circle];\n", state));
             }
         }
 
-        for (from, map) in &self.transitions {
-            for (&symbol, targets) in map {
-                let label = if symbol == '\0' {
-                    "ε".to_string()
-                } else {
-                    symbol.to_string()
+        for (from, edges) in self.transitions.iter().enumerate() {
+            for (symbol, to) in edges {
+                let label = match symbol {
+                    Symbol::Epsilon => "ε".to_string(),
+                    Symbol::Save(slot) if slot % 2 == 0 => format!("ε ({}", slot / 2),
+                    Symbol::Save(slot) => format!("ε ){}", slot / 2),
+                    Symbol::Look(look) => escape_label(&look.to_string()),
+                    Symbol::Class(class) => escape_label(&class.to_string()),
                 };
-                for to in targets {
-                    dot.push_str(&format!("\t{} -> {} [label=\"{}\"];\n", from, to, label));
-                }
+                dot.push_str(&format!("\tq{} -> q{} [label=\"{}\"];\n", from, to, label));
             }
         }
 
@@ -56,7 +62,7 @@ impl NFA {
 
         self.write_dot_file(&dot_path);
         Command::new("dot")
-            .args(&["-Tpng", dot_path.to_str().unwrap(), "-o", png_path.to_str().unwrap()])
+            .args(["-Tpng", dot_path.to_str().unwrap(), "-o", png_path.to_str().unwrap()])
             .status()
             .expect("Failed to run dot command");
 
@@ -71,34 +77,40 @@ impl NFA {
 // So I'm just going to copy paste the code and make a few changes
 impl DFA {
     fn to_dot(&self) -> String {
-        let mut id_map: HashMap<Vec<String>, String> = HashMap::new();
-        for (i, state) in self.states.iter().enumerate() {
-            id_map.insert(state.clone(), format!("S{}", i));
-        }
-
         let mut dot = String::new();
         dot.push_str("digraph DFA {\n");
         dot.push_str("    rankdir=LR;\n");
         dot.push_str("    node [shape=point]; start;\n");
-        dot.push_str(&format!("    start -> {} ;\n", &id_map[&self.q0]));
-
-        for state in &self.states {
-            let id = &id_map[state];
-            let label = state.join(",");
-            
-            if self.final_states.contains(state) {
-                dot.push_str(&format!("    {} [shape=doublecircle, label=\"{}\"];\n", id, label));
+        dot.push_str(&format!("    start -> S{} ;\n", self.q0));
+
+        // DEAD is left out along with every edge into it, which for a complete
+        // DFA are only the ones on characters outside its alphabet
+        for state in (DEAD + 1)..self.state_count() {
+            // Show the NFA subset a state stands for when we still know it
+            let label = match &self.labels {
+                Some(labels) if labels[state].is_empty() => "∅".to_string(),
+                Some(labels) => labels[state].iter().map(|q| format!("q{}", q)).collect::<Vec<_>>().join(","),
+                None => format!("S{}", state),
+            };
+
+            if self.final_states[state] {
+                dot.push_str(&format!("    S{} [shape=doublecircle, label=\"{}\"];\n", state, label));
             } else {
-                dot.push_str(&format!("    {} [shape=circle, label=\"{}\"];\n", id, label));
+                dot.push_str(&format!("    S{} [shape=circle, label=\"{}\"];\n", state, label));
             }
         }
 
-        for (state, map) in &self.transitions {
-            let from_id = &id_map[state];
-            for (&symbol, targets) in map {
-                let to_id = &id_map[targets];
-                let label = symbol.to_string();
-                dot.push_str(&format!("    {} -> {} [label=\"{}\"];\n", from_id, to_id, label));
+        for (from, targets) in self.transitions.iter().enumerate().skip(DEAD + 1) {
+            // Classes leading to the same state are drawn as one edge labelled with their union
+            let mut edges: HashMap<usize, Vec<(char, char)>> = HashMap::new();
+            for (index, &to) in targets.iter().enumerate().filter(|&(_, &to)| to != DEAD) {
+                edges.entry(to).or_default()
+                    .extend_from_slice(self.alphabet.class(index).ranges());
+            }
+
+            for (to, ranges) in edges {
+                let label = escape_label(&CharClass::new(ranges).to_string());
+                dot.push_str(&format!("    S{} -> S{} [label=\"{}\"];\n", from, to, label));
             }
         }
 
@@ -123,7 +135,7 @@ impl DFA {
 
         self.write_dot_file(&dot_path);
         Command::new("dot")
-            .args(&["-Tpng", dot_path.to_str().unwrap(), "-o", png_path.to_str().unwrap()])
+            .args(["-Tpng", dot_path.to_str().unwrap(), "-o", png_path.to_str().unwrap()])
             .status()
             .expect("Failed to run dot command");
 
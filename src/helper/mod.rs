@@ -1,3 +1,4 @@
+pub mod ast;
 pub mod parser;
 pub mod graphviz;
 pub mod thompson;
\ No newline at end of file
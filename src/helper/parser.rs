@@ -1,29 +1,394 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+use std::fmt;
+use crate::charclass::CharClass;
+use crate::nfa::Look;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Char(char),
+    Class(CharClass), // [...]
+    Look(Look), // ^, $, \b, \B
     Union,      // |
     Star,       // *
     Plus,       // +
     Question,   // ?
+    Repeat { min: usize, max: Option<usize> }, // {n}, {n,}, {n,m}
     Concat,     // explicit concatenation
-    LeftParen,  // (
+    LeftParen(Group), // (, (?:, (?<name>
     RightParen, // )
 }
 
-pub fn tokenize(regex: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
+// What kind of group a `(` opens
+#[derive(Debug, Clone, PartialEq)]
+pub enum Group {
+    Capturing,     // (
+    Named(String), // (?<name> or (?P<name>
+    NonCapturing,  // (?:
+}
+
+// Characters that have to be escaped with `\\` to be matched literally
+fn is_meta(c: char) -> bool {
+    matches!(c, '(' | ')' | '*' | '+' | '?' | '|' | '[' | '{' | '.' | '^' | '$' | '\\')
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Char(c) if is_meta(*c) => write!(f, "\\{}", c),
+            Token::Char('\n') => write!(f, "\\n"),
+            Token::Char('\t') => write!(f, "\\t"),
+            Token::Char('\r') => write!(f, "\\r"),
+            Token::Char(c) if c.is_control() => write!(f, "\\u{{{:x}}}", *c as u32),
+            Token::Char(c) => write!(f, "{}", c),
+            Token::Class(class) => write!(f, "{}", class),
+            Token::Look(look) => write!(f, "{}", look),
+            Token::Union => write!(f, "|"),
+            Token::Star => write!(f, "*"),
+            Token::Plus => write!(f, "+"),
+            Token::Question => write!(f, "?"),
+            Token::Repeat { min, max: Some(max) } if min == max => write!(f, "{{{}}}", min),
+            Token::Repeat { min, max: Some(max) } => write!(f, "{{{},{}}}", min, max),
+            Token::Repeat { min, max: None } => write!(f, "{{{},}}", min),
+            Token::Concat => write!(f, "·"),
+            Token::LeftParen(Group::Capturing) => write!(f, "("),
+            Token::LeftParen(Group::Named(name)) => write!(f, "(?<{}>", name),
+            Token::LeftParen(Group::NonCapturing) => write!(f, "(?:"),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+// A token together with the byte range it covers in the regex.
+// Implicit concatenations are empty and sit right before the token that follows them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub offset: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    UnclosedParen,
+    UnopenedParen,
+    EmptyGroup,
+    DanglingQuantifier,
+    EmptyAlternative,
+    TrailingUnion,
+    TrailingBackslash,
+    UnknownEscape,
+    InvalidCodePoint,
+    UnclosedBracket,
+    InvalidRange,
+    LookInClass,
+    InvalidRepetition,
+    InvalidGroup,
+    InvalidGroupName,
+    DuplicateGroupName,
+    RepetitionTooLarge { states: usize, limit: usize },
+    MissingOperand,
+    MissingOperator,
+    UnexpectedToken,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub token: Token,
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError {
+    pub(crate) fn new(kind: ParseErrorKind, spanned: Spanned) -> ParseError {
+        ParseError {
+            offset: spanned.offset,
+            token: spanned.token,
+            kind,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match &self.kind {
+            ParseErrorKind::UnclosedParen => "unbalanced `(`: group is never closed".to_string(),
+            ParseErrorKind::UnopenedParen => "unbalanced `)`: no group to close".to_string(),
+            ParseErrorKind::EmptyGroup => format!("empty group `{})`", self.token),
+            ParseErrorKind::DanglingQuantifier => format!("dangling quantifier `{}`: nothing to repeat", self.token),
+            ParseErrorKind::EmptyAlternative => "empty alternative before `|`".to_string(),
+            ParseErrorKind::TrailingUnion => "trailing `|`: alternative is empty".to_string(),
+            ParseErrorKind::TrailingBackslash => "trailing `\\`: nothing to escape".to_string(),
+            ParseErrorKind::UnknownEscape => format!("unknown escape sequence `\\{}`", self.token),
+            ParseErrorKind::InvalidCodePoint => "escape is not a valid `\\xHH` or `\\u{...}` code point".to_string(),
+            ParseErrorKind::UnclosedBracket => "unbalanced `[`: class is never closed".to_string(),
+            ParseErrorKind::InvalidRange => format!("invalid range ending in `{}`", self.token),
+            ParseErrorKind::LookInClass => format!("assertion `{}` can't be used inside a class", self.token),
+            ParseErrorKind::InvalidRepetition => "expected `{n}`, `{n,}` or `{n,m}` with n <= m".to_string(),
+            ParseErrorKind::InvalidGroup => "expected `(?:`, `(?<name>` or `(?P<name>` after `(?`".to_string(),
+            ParseErrorKind::InvalidGroupName => "group name must be a letter or `_` followed by letters, digits or `_`".to_string(),
+            ParseErrorKind::DuplicateGroupName => format!("group `{}` is defined more than once", self.token),
+            ParseErrorKind::RepetitionTooLarge { states, limit } => {
+                format!("repetition `{}` would expand to {} states, over the limit of {}", self.token, states, limit)
+            }
+            ParseErrorKind::MissingOperand => format!("operator `{}` is missing an operand", self.token),
+            ParseErrorKind::MissingOperator => format!("operand `{}` is not joined to the rest of the regex", self.token),
+            ParseErrorKind::UnexpectedToken => format!("unexpected token `{}`", self.token),
+        }
+    }
+
+    // Renders the regex with a caret under the offending token, e.g.
+    //   a|*b
+    //     ^
+    pub fn caret(&self, regex: &str) -> String {
+        let column = regex[..self.offset.min(regex.len())].chars().count();
+        format!("{}\n{}^", regex, " ".repeat(column))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message(), self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type CharIndices<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+// Reads exactly `count` hex digits (or as many as there are, up to `count`, if `exact` is false)
+fn read_hex(chars: &mut CharIndices, count: usize, exact: bool) -> Option<u32> {
+    let mut value: u32 = 0;
+    let mut read: usize = 0;
+
+    while read < count {
+        match chars.peek().and_then(|&(_, c)| c.to_digit(16)) {
+            Some(digit) => {
+                value = value * 16 + digit;
+                read += 1;
+                chars.next();
+            }
+            None => break,
+        }
+    }
+
+    if read == 0 || (exact && read < count) {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+// Decodes the escape sequence following a `\\` at `offset` into the character it stands for,
+// or into a class for the shorthands `\d`, `\w`, `\s` and their negations `\D`, `\W`, `\S`
+fn parse_escape(chars: &mut CharIndices, offset: usize) -> Result<Token, ParseError> {
+    let error = |kind: ParseErrorKind, c: char| {
+        Err(ParseError::new(kind, Spanned { token: Token::Char(c), offset, end: offset }))
+    };
+
+    let c = match chars.next() {
+        Some((_, c)) => c,
+        None => return error(ParseErrorKind::TrailingBackslash, '\\'),
+    };
+
+    let code_point = match c {
+        'd' => return Ok(Token::Class(CharClass::digit())),
+        'D' => return Ok(Token::Class(CharClass::digit().negate())),
+        'w' => return Ok(Token::Class(CharClass::word())),
+        'W' => return Ok(Token::Class(CharClass::word().negate())),
+        's' => return Ok(Token::Class(CharClass::space())),
+        'S' => return Ok(Token::Class(CharClass::space().negate())),
+        'n' => return Ok(Token::Char('\n')),
+        't' => return Ok(Token::Char('\t')),
+        'r' => return Ok(Token::Char('\r')),
+        'b' => return Ok(Token::Look(Look::WordBoundary)),
+        'B' => return Ok(Token::Look(Look::NotWordBoundary)),
+        'x' => read_hex(chars, 2, true),
+        'u' => {
+            // \u{...} takes one to six hex digits between braces
+            if chars.next_if(|&(_, c)| c == '{').is_none() {
+                return error(ParseErrorKind::InvalidCodePoint, c);
+            }
+            let value = read_hex(chars, 6, false);
+            if chars.next_if(|&(_, c)| c == '}').is_none() {
+                return error(ParseErrorKind::InvalidCodePoint, c);
+            }
+            value
+        }
+        // Any other punctuation is taken literally, which also covers the metacharacters
+        _ if c.is_ascii_punctuation() => return Ok(Token::Char(c)),
+        _ => return error(ParseErrorKind::UnknownEscape, c),
+    };
+
+    match code_point.and_then(char::from_u32) {
+        Some(decoded) => Ok(Token::Char(decoded)),
+        None => error(ParseErrorKind::InvalidCodePoint, c),
+    }
+}
+
+// Reads one class member after a `[`, decoding escapes the same way as outside a class
+fn parse_class_char(chars: &mut CharIndices, open: usize) -> Result<(usize, Token), ParseError> {
+    match chars.next() {
+        Some((offset, '\\')) => match parse_escape(chars, offset)? {
+            Token::Look(look) => Err(ParseError::new(ParseErrorKind::LookInClass, Spanned { token: Token::Look(look), offset, end: offset })),
+            token => Ok((offset, token)),
+        },
+        Some((offset, c)) => Ok((offset, Token::Char(c))),
+        None => Err(ParseError::new(ParseErrorKind::UnclosedBracket, Spanned { token: Token::Char('['), offset: open, end: open })),
+    }
+}
+
+// Parses a bracket class whose `[` sits at `open`, e.g. `[abc]`, `[a-z0-9_]` or `[^\n]`.
+// A `]` right after the opening bracket and a `-` at either end are taken literally.
+fn parse_class(chars: &mut CharIndices, open: usize) -> Result<CharClass, ParseError> {
+    let negated: bool = chars.next_if(|&(_, c)| c == '^').is_some();
+    let mut ranges: Vec<(char, char)> = Vec::new();
+    let mut first: bool = true;
+
+    loop {
+        if !first && chars.next_if(|&(_, c)| c == ']').is_some() {
+            break;
+        }
+        first = false;
+
+        let (offset, start) = match parse_class_char(chars, open)? {
+            (_, Token::Class(class)) => {
+                ranges.extend_from_slice(class.ranges());
+                continue;
+            }
+            (offset, Token::Char(c)) => (offset, c),
+            _ => unreachable!("class members are characters or shorthand classes"),
+        };
+        let is_range = chars.peek().map(|&(_, c)| c) == Some('-')
+            && chars.clone().nth(1).map(|(_, c)| c).is_some_and(|c| c != ']');
+
+        if is_range {
+            chars.next();
+            let end = match parse_class_char(chars, open)? {
+                (_, Token::Char(c)) if c >= start => c,
+                (_, token) => {
+                    return Err(ParseError::new(ParseErrorKind::InvalidRange, Spanned { token, offset, end: offset }));
+                }
+            };
+            ranges.push((start, end));
+        } else {
+            ranges.push((start, start));
+        }
+    }
+
+    let class = CharClass::new(ranges);
+    Ok(if negated { class.negate() } else { class })
+}
+
+fn invalid_repetition(offset: usize) -> ParseError {
+    ParseError::new(ParseErrorKind::InvalidRepetition, Spanned { token: Token::Char('{'), offset, end: offset })
+}
+
+// Reads a bound of the repetition whose `{` sits at `offset`, rejecting one
+// too large for a usize instead of quoting a bound that wasn't written
+fn read_number(chars: &mut CharIndices, offset: usize) -> Result<Option<usize>, ParseError> {
+    let mut value: Option<usize> = None;
+    while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+        let digit = c.to_digit(10).unwrap() as usize;
+        let next: Option<usize> = value.unwrap_or(0).checked_mul(10).and_then(|value| value.checked_add(digit));
+        value = Some(next.ok_or_else(|| invalid_repetition(offset))?);
+    }
+    Ok(value)
+}
+
+// Parses a counted repetition whose `{` sits at `offset`
+fn parse_repetition(chars: &mut CharIndices, offset: usize) -> Result<Token, ParseError> {
+    let min: Option<usize> = read_number(chars, offset)?;
+    let max: Option<usize> = if chars.next_if(|&(_, c)| c == ',').is_some() {
+        read_number(chars, offset)?
+    } else {
+        min
+    };
+
+    match (min, max, chars.next()) {
+        (Some(min), max, Some((_, '}'))) if max.is_none_or(|max| min <= max) => Ok(Token::Repeat { min, max }),
+        _ => Err(invalid_repetition(offset)),
+    }
+}
+
+// Parses what follows a `(` at `offset`: nothing for a plain group, or the `?:`,
+// `?<name>` or `?P<name>` that turns it into a non-capturing or named one
+fn parse_group(chars: &mut CharIndices, offset: usize) -> Result<Group, ParseError> {
+    let error = |kind: ParseErrorKind| {
+        Err(ParseError::new(kind, Spanned { token: Token::LeftParen(Group::Capturing), offset, end: offset }))
+    };
+
+    if chars.next_if(|&(_, c)| c == '?').is_none() {
+        return Ok(Group::Capturing);
+    }
+    if chars.next_if(|&(_, c)| c == ':').is_some() {
+        return Ok(Group::NonCapturing);
+    }
+    chars.next_if(|&(_, c)| c == 'P');
+    if chars.next_if(|&(_, c)| c == '<').is_none() {
+        return error(ParseErrorKind::InvalidGroup);
+    }
+
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '>')) => break,
+            Some((_, c)) if c == '_' || c.is_alphanumeric() => name.push(c),
+            _ => return error(ParseErrorKind::InvalidGroupName),
+        }
+    }
+
+    match name.chars().next() {
+        Some(first) if !first.is_numeric() => Ok(Group::Named(name)),
+        _ => error(ParseErrorKind::InvalidGroupName),
+    }
+}
+
+// Splits the regex into tokens and inserts the implicit concatenations.
+// Only the syntax of single tokens is checked here; whether they fit
+// together, e.g. that parentheses are balanced, is up to `ast::parse`.
+pub fn tokenize(regex: &str) -> Result<Vec<Spanned>, ParseError> {
+    match tokens_before_error(regex) {
+        (tokens, None) => Ok(tokens),
+        (_, Some(err)) => Err(err),
+    }
+}
+
+// The tokens up to the first token that can't be read, and the error it
+// gives. The parser still looks at those tokens, as they may hold an error
+// that comes earlier in the regex.
+pub(crate) fn tokens_before_error(regex: &str) -> (Vec<Spanned>, Option<ParseError>) {
+    let mut tokens: Vec<Spanned> = Vec::new();
+    let error: Option<ParseError> = read_tokens(regex, &mut tokens).err();
+    (tokens, error)
+}
+
+fn read_tokens(regex: &str, tokens: &mut Vec<Spanned>) -> Result<(), ParseError> {
     let mut prev_was_char = false;
+    let mut names: Vec<String> = Vec::new();
+    let mut chars: CharIndices = regex.char_indices().peekable();
 
-    for c in regex.chars() {
+    while let Some((offset, c)) = chars.next() {
         let token = match c {
-            '(' => Token::LeftParen,
+            '(' => Token::LeftParen(parse_group(&mut chars, offset)?),
             ')' => Token::RightParen,
             '*' => Token::Star,
             '+' => Token::Plus,
             '?' => Token::Question,
+            '{' => parse_repetition(&mut chars, offset)?,
             '|' => Token::Union,
+            '[' => Token::Class(parse_class(&mut chars, offset)?),
+            '.' => Token::Class(CharClass::dot()),
+            '^' => Token::Look(Look::Start),
+            '$' => Token::Look(Look::End),
+            '\\' => parse_escape(&mut chars, offset)?,
             _ => Token::Char(c),
         };
+        let end: usize = chars.peek().map_or(regex.len(), |&(end, _)| end);
+        let spanned = Spanned { token: token.clone(), offset, end };
+
+        if let Token::LeftParen(Group::Named(name)) = &token {
+            if names.contains(name) {
+                return Err(ParseError::new(ParseErrorKind::DuplicateGroupName, spanned));
+            }
+            names.push(name.clone());
+        }
 
         // Handle explicit concatenation
         // If the previous token was a character or a right parenthesis,
@@ -31,115 +396,178 @@ pub fn tokenize(regex: &str) -> Vec<Token> {
         // we need to insert an explicit concatenation token
         if prev_was_char {
             match token {
-                Token::Char(_) | Token::LeftParen => {
-                    tokens.push(Token::Concat);
+                Token::Char(_) | Token::Class(_) | Token::Look(_) | Token::LeftParen(_) => {
+                    tokens.push(Spanned { token: Token::Concat, offset, end: offset });
                 }
                 _ => {}
             }
         }
 
-        tokens.push(token);
+        tokens.push(spanned);
 
-        prev_was_char = matches!(token, Token::Char(_) | Token::RightParen | Token::Star | Token::Plus | Token::Question);
+        prev_was_char = matches!(token, Token::Char(_) | Token::Class(_) | Token::Look(_) | Token::RightParen | Token::Star | Token::Plus | Token::Question | Token::Repeat { .. });
     }
 
-    tokens
+    Ok(())
 }
 
 fn precedence(token: &Token) -> u8 {
     match token {
-        Token::Star | Token::Plus | Token::Question => 3,
+        Token::Star | Token::Plus | Token::Question | Token::Repeat { .. } => 3,
         Token::Concat => 2,
         Token::Union => 1,
         _ => 0,
     }
 }
 
-pub fn to_postfix(tokens: Vec<Token>) -> Vec<Token> {
+pub fn to_postfix(tokens: Vec<Spanned>) -> Result<Vec<Spanned>, ParseError> {
     let mut output = Vec::new();
-    let mut stack = Vec::new();
+    let mut stack: Vec<Spanned> = Vec::new();
 
-    for token in tokens {
-        match token {
-            Token::Char(_) => output.push(token),
-            Token::LeftParen => stack.push(token),
+    for spanned in tokens {
+        match spanned.token {
+            Token::Char(_) | Token::Class(_) | Token::Look(_) => output.push(spanned),
+            Token::LeftParen(_) => stack.push(spanned),
             Token::RightParen => {
-                while let Some(top) = stack.pop() {
-                    if top == Token::LeftParen {
-                        break;
+                loop {
+                    match stack.pop() {
+                        Some(top) if matches!(top.token, Token::LeftParen(_)) => break,
+                        Some(top) => output.push(top),
+                        None => return Err(ParseError::new(ParseErrorKind::UnopenedParen, spanned)),
                     }
-                    output.push(top);
                 }
             }
             _ => {
                 while let Some(top) = stack.last() {
-                    if precedence(&top) >= precedence(&token) {
+                    if precedence(&top.token) >= precedence(&spanned.token) {
                         output.push(stack.pop().unwrap());
                     } else {
                         break;
                     }
                 }
-                stack.push(token);
+                stack.push(spanned);
             }
         }
     }
 
     while let Some(top) = stack.pop() {
+        if matches!(top.token, Token::LeftParen(_)) {
+            return Err(ParseError::new(ParseErrorKind::UnclosedParen, top));
+        }
         output.push(top);
     }
 
-    output
+    Ok(output)
 }
 
-use core::panic;
-
 use crate::nfa::NFA;
+use crate::helper::ast::Ast;
 use crate::helper::thompson;
 
-pub fn build_nfa(tokens: Vec<Token>) -> NFA {
-    let mut stack = Vec::new();
+// How many NFA states a single counted repetition may expand to
+pub const DEFAULT_REPEAT_LIMIT: usize = 1000;
 
-    for token in tokens {
-        match token {
-            Token::Char(c) => {
-                let nfa = NFA::from_char(c);
-                stack.push(nfa);
-            }
-            Token::Union => {
-                let nfa2 = stack.pop().unwrap();
-                let nfa1 = stack.pop().unwrap();
-                let nfa = thompson::union(nfa1, nfa2);
-                stack.push(nfa);
+pub fn build_nfa(ast: &Ast) -> Result<NFA, ParseError> {
+    build_nfa_with_limit(ast, DEFAULT_REPEAT_LIMIT)
+}
+
+// Same as `build_nfa`, but rejects any `{n,m}` that would copy its operand
+// into more than `repeat_limit` states. `*`, `+` and `?` never need more than
+// two copies and are always allowed.
+pub fn build_nfa_with_limit(ast: &Ast, repeat_limit: usize) -> Result<NFA, ParseError> {
+    let mut nfa = build(ast, repeat_limit)?;
+    nfa.group_names = vec![None; group_count(ast)];
+    collect_group_names(ast, &mut nfa.group_names);
+    Ok(nfa)
+}
+
+// Number of capture groups the parser opened, counting group 0. A group under
+// `{0}` leaves no `Save` in the NFA, but still takes up its index.
+fn group_count(ast: &Ast) -> usize {
+    match ast {
+        Ast::Empty { .. } | Ast::Literal { .. } | Ast::Class { .. } | Ast::Look { .. } => 1,
+        Ast::Concat { items: children, .. } | Ast::Alternation { alternatives: children, .. } => {
+            children.iter().map(group_count).max().unwrap_or(1)
+        }
+        Ast::Repeat { ast, .. } => group_count(ast),
+        Ast::Group { ast, index, .. } => group_count(ast).max(index.map_or(1, |index| index + 1)),
+    }
+}
+
+// Records the name of every named group in the tree at its index
+fn collect_group_names(ast: &Ast, names: &mut [Option<String>]) {
+    match ast {
+        Ast::Empty { .. } | Ast::Literal { .. } | Ast::Class { .. } | Ast::Look { .. } => {}
+        Ast::Concat { items: children, .. } | Ast::Alternation { alternatives: children, .. } => {
+            for child in children {
+                collect_group_names(child, names);
             }
-            Token::Star => {
-                let nfa = stack.pop().unwrap();
-                let nfa = thompson::star(nfa);
-                stack.push(nfa);
+        }
+        Ast::Repeat { ast, .. } => collect_group_names(ast, names),
+        Ast::Group { ast, index, name, .. } => {
+            if let (Some(index), Some(name)) = (index, name) {
+                names[*index] = Some(name.clone());
             }
-            Token::Plus => {
-                // a+ = a* a
-                let nfa = stack.pop().unwrap();
-                let nfa_clone = nfa.clone();
-                let nfa_clone = thompson::star(nfa_clone);
-                let nfa = thompson::concat(nfa, nfa_clone);
-                stack.push(nfa);
+            collect_group_names(ast, names);
+        }
+    }
+}
+
+fn build(ast: &Ast, repeat_limit: usize) -> Result<NFA, ParseError> {
+    let nfa = match ast {
+        Ast::Empty { .. } => NFA::epsilon(),
+        Ast::Literal { c, .. } => NFA::from_char(*c),
+        Ast::Class { class, .. } => NFA::from_class(class.clone()),
+        Ast::Look { look, .. } => NFA::look(*look),
+        Ast::Group { ast, index: None, .. } => build(ast, repeat_limit)?,
+        Ast::Group { ast, index: Some(index), .. } => {
+            // (a) = save(2i) a save(2i + 1)
+            let nfa = build(ast, repeat_limit)?;
+            let nfa = thompson::concat(NFA::save(2 * index), nfa);
+            thompson::concat(nfa, NFA::save(2 * index + 1))
+        }
+        Ast::Concat { items, .. } => {
+            let mut nfa = build(&items[0], repeat_limit)?;
+            for item in &items[1..] {
+                nfa = thompson::concat(nfa, build(item, repeat_limit)?);
             }
-            Token::Question => {
-                // a? = a* | ε
-                let nfa = stack.pop().unwrap();
-                let epsilon_nfa = NFA::from_char('\0');
-                let nfa = thompson::union(nfa, epsilon_nfa);
-                stack.push(nfa);
+            nfa
+        }
+        Ast::Alternation { alternatives, .. } => {
+            let mut nfa = build(&alternatives[0], repeat_limit)?;
+            for alternative in &alternatives[1..] {
+                nfa = thompson::union(nfa, build(alternative, repeat_limit)?);
             }
-            Token::Concat => {
-                let nfa2 = stack.pop().unwrap();
-                let nfa1 = stack.pop().unwrap();
-                let nfa = thompson::concat(nfa1, nfa2);
-                stack.push(nfa);
+            nfa
+        }
+        Ast::Repeat { ast: inner, min, max, .. } => {
+            let nfa = build(inner, repeat_limit)?;
+            match (min, max) {
+                // a* keeps the single-copy construction
+                (0, None) => thompson::star(nfa),
+                // a+ = a a*
+                (1, None) => {
+                    let nfa_clone = thompson::star(nfa.clone());
+                    thompson::concat(nfa, nfa_clone)
+                }
+                // a? = a | ε
+                (0, Some(1)) => thompson::union(nfa, NFA::epsilon()),
+                _ => {
+                    // Unbounded repetitions need one extra copy for the trailing star
+                    let copies: usize = max.unwrap_or(min.saturating_add(1));
+                    let states: usize = copies.saturating_mul(nfa.state_count());
+                    if states > repeat_limit {
+                        // The quantifier starts right where its operand ends
+                        let offset = inner.span().end;
+                        let token = Token::Repeat { min: *min, max: *max };
+                        let kind = ParseErrorKind::RepetitionTooLarge { states, limit: repeat_limit };
+                        return Err(ParseError::new(kind, Spanned { token, offset, end: ast.span().end }));
+                    }
+                    thompson::repeat(nfa, *min, *max)
+                }
             }
-            _ => panic!("Unexpected token: {:?}", token),
         }
-    }
+    };
 
-    stack.pop().unwrap()
-}
\ No newline at end of file
+    Ok(nfa)
+}
@@ -1,35 +1,27 @@
-use crate::nfa::NFA;
-use std::collections::HashMap;
+use crate::nfa::{NFA, Symbol};
+use crate::charclass::CharClass;
+
+fn merge_sigma(mut sigma1: Vec<CharClass>, sigma2: Vec<CharClass>) -> Vec<CharClass> {
+    sigma1.extend(sigma2);
+    sigma1.sort();
+    sigma1.dedup();
+    sigma1
+}
 
 pub fn concat(nfa1: NFA, mut nfa2: NFA) -> NFA {
-    let mut transitions = nfa1.transitions.clone();
-    
-    nfa2 = nfa2.rename_states(nfa1.states.len());
+    let mut transitions = nfa1.transitions;
+
+    nfa2 = nfa2.rename_states(transitions.len());
 
     // Add epsilon transitions from the final states of nfa1 to the initial state of nfa2
     for final_state in nfa1.final_states {
-        transitions
-            .entry(final_state).or_insert_with(HashMap::new)
-            .entry('\0').or_insert_with(Vec::new)
-            .push(nfa2.q0.clone());
+        transitions[final_state].push((Symbol::Epsilon, nfa2.q0));
     }
 
-    for (state, map) in nfa2.transitions {
-        transitions.insert(state, map);
-    }
-
-    let mut states = nfa1.states.clone();
-    states.extend(nfa2.states.clone());
-
-    let mut sigma = nfa1.sigma.clone();
-    sigma.extend(nfa2.sigma.clone());
-    sigma.sort();
-    sigma.dedup();
-
+    transitions.extend(nfa2.transitions);
 
     NFA::new(
-        sigma,
-        states,
+        merge_sigma(nfa1.sigma, nfa2.sigma),
         nfa1.q0,
         nfa2.final_states,
         transitions
@@ -37,87 +29,77 @@ pub fn concat(nfa1: NFA, mut nfa2: NFA) -> NFA {
 }
 
 pub fn union(mut nfa1: NFA, mut nfa2: NFA) -> NFA {
-    let total_states = nfa1.states.len() + nfa2.states.len();
-    let start = String::from("q0");
-    let end = format!("q{}", total_states + 1);
+    let start: usize = 0;
+    let end: usize = nfa1.state_count() + nfa2.state_count() + 1;
 
     nfa1 = nfa1.rename_states(1);
-    nfa2 = nfa2.rename_states(nfa1.states.len() + 1);
-
-    let mut transitions: HashMap<String, HashMap<char, Vec<String>>> = HashMap::new();
-    transitions.insert(start.clone(), {
-        let mut map = HashMap::new();
-        map.insert('\0', vec![nfa1.q0, nfa2.q0]);
-        map
-    });
-
-    for (state, map) in nfa1.transitions.into_iter().chain(nfa2.transitions.into_iter()) {
-        transitions.insert(state, map);
-    }
-
-    for final_state in nfa1.final_states {
-        transitions.entry(final_state).or_insert_with(HashMap::new)
-            .entry('\0').or_insert_with(Vec::new)
-            .push(end.clone());
-    }
-
-    for final_state in nfa2.final_states {
-        transitions.entry(final_state).or_insert_with(HashMap::new)
-            .entry('\0').or_insert_with(Vec::new)
-            .push(end.clone());
+    nfa2 = nfa2.rename_states(nfa1.state_count() + 1);
+
+    let mut transitions: Vec<Vec<(Symbol, usize)>> = vec![vec![
+        (Symbol::Epsilon, nfa1.q0),
+        (Symbol::Epsilon, nfa2.q0),
+    ]];
+    transitions.extend(nfa1.transitions);
+    transitions.extend(nfa2.transitions);
+    transitions.push(vec![]);
+
+    for final_state in nfa1.final_states.into_iter().chain(nfa2.final_states) {
+        transitions[final_state].push((Symbol::Epsilon, end));
     }
 
-    let mut states = vec![start.clone(), end.clone()];
-    states.extend(nfa1.states);
-    states.extend(nfa2.states);
-
-    let mut sigma = nfa1.sigma;
-    sigma.extend(nfa2.sigma);
-    sigma.sort();
-    sigma.dedup();
-
-    NFA {
-        sigma,
-        states,
-        q0: start,
-        final_states: vec![end],
-        transitions,
-    }
+    NFA::new(
+        merge_sigma(nfa1.sigma, nfa2.sigma),
+        start,
+        vec![end],
+        transitions
+    )
 }
 
 pub fn star(mut nfa: NFA) -> NFA {
-    let start = String::from("q0");
-    let end = format!("q{}", nfa.states.len() + 1);
+    let start: usize = 0;
+    let end: usize = nfa.state_count() + 1;
 
     nfa = nfa.rename_states(1);
 
-    let mut transitions = HashMap::new();
-    transitions.insert(start.clone(), {
-        let mut map = HashMap::new();
-        map.insert('\0', vec![nfa.q0.clone(), end.clone()]);
-        map
-    });
-
-    for (state, map) in nfa.transitions {
-        transitions.insert(state, map);
-    }
+    let mut transitions: Vec<Vec<(Symbol, usize)>> = vec![vec![
+        (Symbol::Epsilon, nfa.q0),
+        (Symbol::Epsilon, end),
+    ]];
+    transitions.extend(nfa.transitions);
+    transitions.push(vec![]);
 
+    // Looping back is tried before leaving, so the star is greedy
     for final_state in nfa.final_states {
-        transitions.entry(final_state).or_insert_with(HashMap::new)
-            .entry('\0').or_insert_with(Vec::new)
-            .extend(vec![nfa.q0.clone(), end.clone()]);
+        transitions[final_state].push((Symbol::Epsilon, nfa.q0));
+        transitions[final_state].push((Symbol::Epsilon, end));
     }
 
-    let mut states = vec![start.clone(), end.clone()];
-    states.extend(nfa.states);
-
-    let sigma = nfa.sigma;
+    NFA::new(
+        nfa.sigma,
+        start,
+        vec![end],
+        transitions
+    )
+}
 
-    NFA {
-        sigma,
-        states,
-        q0: start,
-        final_states: vec![end],
-        transitions,
+// a{n,m} = a...a (a(a(...)?)?)? with n mandatory copies followed by m - n nested optional ones,
+// and a{n,} = a...a a*
+pub fn repeat(nfa: NFA, min: usize, max: Option<usize>) -> NFA {
+    let tail: NFA = match max {
+        None => star(nfa.clone()),
+        Some(max) => {
+            let mut optional: NFA = NFA::epsilon();
+            for _ in min..max {
+                optional = union(concat(nfa.clone(), optional), NFA::epsilon());
+            }
+            optional
+        }
+    };
+
+    let mut result: NFA = tail;
+    for _ in 0..min {
+        result = concat(nfa.clone(), result);
     }
-}
\ No newline at end of file
+
+    result
+}
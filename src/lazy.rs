@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::dfa::{Alphabet, Context, Contexts};
+use crate::nfa::{NFA, is_word_char};
+
+// How many bytes of states `NFA::to_lazy_dfa` may cache
+pub const DEFAULT_CACHE_CAPACITY: usize = 1 << 20;
+
+// How often the cache may fill up during a single search before the search
+// gives up on it and simulates the NFA instead
+const MAX_CLEARS: usize = 3;
+
+// The cache filled up too often to be worth rebuilding again
+struct CacheExhausted;
+
+// The states of one lazy DFA computed so far. An unanchored one adds the
+// start subset back into every subset it reaches, so it follows a match
+// starting at every position at once.
+struct Cache {
+    alphabet: Alphabet,
+    contexts: Contexts,
+    start_subset: Vec<usize>,
+    unanchored: bool,
+    ids: HashMap<(Vec<usize>, Context), usize>,
+    states: Vec<(Vec<usize>, Context)>,
+    final_states: Vec<bool>,
+    // transitions[q][i] is the state q goes to on class i and whether
+    // a match ends right before that character, once it has been computed
+    transitions: Vec<Vec<Option<(usize, bool)>>>,
+    // Roughly how many bytes the cached states take
+    memory: usize,
+    capacity: usize,
+    // How often the cache was cleared during the current search
+    clears: usize,
+}
+
+impl Cache {
+    fn new(nfa: &NFA, capacity: usize, unanchored: bool) -> Cache {
+        let contexts: Contexts = Contexts::of(nfa);
+        Cache {
+            alphabet: nfa.alphabet(contexts, None),
+            contexts,
+            start_subset: nfa.start_subset(),
+            unanchored,
+            ids: HashMap::new(),
+            states: Vec::new(),
+            final_states: Vec::new(),
+            transitions: Vec::new(),
+            memory: 0,
+            capacity,
+            clears: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.ids.clear();
+        self.states.clear();
+        self.final_states.clear();
+        self.transitions.clear();
+        self.memory = 0;
+    }
+
+    // Id of the state for `subset`, adding it to the cache if it isn't there yet.
+    // Clearing the cache to make room invalidates every id handed out before.
+    fn state(&mut self, nfa: &NFA, subset: Vec<usize>, context: Context) -> Result<usize, CacheExhausted> {
+        let key = (subset, self.contexts.merge(context));
+        if let Some(&id) = self.ids.get(&key) {
+            return Ok(id);
+        }
+
+        // The subset is stored twice, once as a key, and every state has a row of transitions
+        let size: usize = 2 * key.0.len() * size_of::<usize>() + self.alphabet.class_count() * size_of::<Option<(usize, bool)>>();
+        if !self.states.is_empty() && self.memory + size > self.capacity {
+            self.clears += 1;
+            if self.clears > MAX_CLEARS {
+                return Err(CacheExhausted);
+            }
+            self.clear();
+        }
+
+        let id: usize = self.states.len();
+        let at_end: Vec<usize> = nfa.resolve(&key.0, key.1, None);
+        self.final_states.push(nfa.contains_final(&at_end));
+        self.transitions.push(vec![None; self.alphabet.class_count()]);
+        self.states.push(key.clone());
+        self.ids.insert(key, id);
+        self.memory += size;
+        Ok(id)
+    }
+
+    // The state a search at the edge of the haystack starts in
+    fn start(&mut self, nfa: &NFA) -> Result<usize, CacheExhausted> {
+        self.state(nfa, self.start_subset.clone(), Context::Start)
+    }
+
+    // The state `state` goes to on class `index` and whether a match
+    // ends right before it, computing the transition the first time it's taken
+    fn next_state(&mut self, nfa: &NFA, state: usize, index: usize) -> Result<(usize, bool), CacheExhausted> {
+        if let Some(cached) = self.transitions[state][index] {
+            return Ok(cached);
+        }
+
+        let symbol: char = self.alphabet.representatives[index];
+        let (subset, context) = &self.states[state];
+        let resolved: Vec<usize> = nfa.resolve(subset, *context, Some(is_word_char(symbol)));
+        let matches_before: bool = nfa.contains_final(&resolved);
+        let mut next_subset: Vec<usize> = nfa.step(&resolved, symbol);
+        if self.unanchored {
+            next_subset.extend(&self.start_subset);
+            next_subset.sort();
+            next_subset.dedup();
+        }
+
+        let clears: usize = self.clears;
+        let next: usize = self.state(nfa, next_subset, Context::after(symbol))?;
+        // If the cache was just cleared, `state` went with it
+        if self.clears == clears {
+            self.transitions[state][index] = Some((next, matches_before));
+        }
+        Ok((next, matches_before))
+    }
+}
+
+// A DFA built while searching: a state and its transitions are only computed
+// the first time the input reaches them, from the same subsets `NFA::to_dfa`
+// would build. A search is one unanchored pass, or for `find` one pass
+// backwards over the reversed NFA to find where the leftmost match starts
+// and one forwards from there to find where it ends.
+//
+// Each cache is thrown away whenever it grows past its share of the
+// capacity, and a search that fills one up too often finishes on the NFA
+// simulation, so regexes whose DFA would explode still match in O(n·m) time.
+pub struct LazyDFA<'n> {
+    nfa: &'n NFA,
+    reverse: NFA,
+    // Anchored at the start of a match, to find its end
+    forward: Cache,
+    // Tells whether anything matches at all
+    unanchored: Cache,
+    // Unanchored over `reverse`, to find where the leftmost match starts
+    backward: Cache,
+}
+
+impl NFA {
+    pub fn to_lazy_dfa(&self) -> LazyDFA<'_> {
+        LazyDFA::new(self, DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+impl<'n> LazyDFA<'n> {
+    // A lazy DFA that keeps at most about `capacity` bytes of states,
+    // shared evenly between its three caches
+    pub fn new(nfa: &'n NFA, capacity: usize) -> LazyDFA<'n> {
+        let reverse: NFA = nfa.reverse();
+        LazyDFA {
+            nfa,
+            forward: Cache::new(nfa, capacity / 3, false),
+            unanchored: Cache::new(nfa, capacity / 3, true),
+            backward: Cache::new(&reverse, capacity / 3, true),
+            reverse,
+        }
+    }
+
+    fn reset_clears(&mut self) {
+        self.forward.clears = 0;
+        self.unanchored.clears = 0;
+        self.backward.clears = 0;
+    }
+
+    // Same as `DFA::longest_match_at`, except that only the empty subset is
+    // known to be dead
+    fn match_at(&mut self, haystack: &str, start: usize) -> Result<Option<usize>, CacheExhausted> {
+        let cache: &mut Cache = &mut self.forward;
+        let mut current_state: usize = cache.state(self.nfa, cache.start_subset.clone(), Context::at(haystack, start))?;
+        let mut end: Option<usize> = None;
+
+        for (offset, c) in haystack[start..].char_indices() {
+            let (next, matches_before) = cache.next_state(self.nfa, current_state, cache.alphabet.class_of(c))?;
+            if matches_before {
+                end = Some(start + offset);
+            }
+            current_state = next;
+            if cache.states[current_state].0.is_empty() {
+                return Ok(end);
+            }
+        }
+
+        if cache.final_states[current_state] {
+            end = Some(haystack.len());
+        }
+        Ok(end)
+    }
+
+    // Where the leftmost match starts. Reading `haystack` backwards, a match
+    // of the reversed NFA that ends right before `c` is a match that starts
+    // right after `c`, so the last one seen is the leftmost.
+    fn leftmost_start(&mut self, haystack: &str) -> Result<Option<usize>, CacheExhausted> {
+        let cache: &mut Cache = &mut self.backward;
+        let mut current_state: usize = cache.start(&self.reverse)?;
+        let mut leftmost: Option<usize> = None;
+        let mut pos: usize = haystack.len();
+
+        for c in haystack.chars().rev() {
+            let (next, matches_before) = cache.next_state(&self.reverse, current_state, cache.alphabet.class_of(c))?;
+            if matches_before {
+                leftmost = Some(pos);
+            }
+            current_state = next;
+            pos -= c.len_utf8();
+        }
+
+        if cache.final_states[current_state] {
+            leftmost = Some(0);
+        }
+        Ok(leftmost)
+    }
+
+    fn try_find(&mut self, haystack: &str) -> Result<Option<Range<usize>>, CacheExhausted> {
+        let Some(start) = self.leftmost_start(haystack)? else {
+            return Ok(None);
+        };
+        Ok(self.match_at(haystack, start)?.map(|end| start..end))
+    }
+
+    fn try_is_match(&mut self, haystack: &str) -> Result<bool, CacheExhausted> {
+        let cache: &mut Cache = &mut self.unanchored;
+        let mut current_state: usize = cache.start(self.nfa)?;
+
+        for c in haystack.chars() {
+            let (next, matches_before) = cache.next_state(self.nfa, current_state, cache.alphabet.class_of(c))?;
+            if matches_before {
+                return Ok(true);
+            }
+            current_state = next;
+        }
+
+        Ok(cache.final_states[current_state])
+    }
+
+    // Finds the leftmost-longest match, like `DFA::find`
+    pub fn find(&mut self, haystack: &str) -> Option<Range<usize>> {
+        self.reset_clears();
+        match self.try_find(haystack) {
+            Ok(found) => found,
+            Err(CacheExhausted) => self.nfa.find(haystack),
+        }
+    }
+
+    // Whether any substring of `haystack` is accepted
+    pub fn is_match(&mut self, haystack: &str) -> bool {
+        self.reset_clears();
+        match self.try_is_match(haystack) {
+            Ok(found) => found,
+            Err(CacheExhausted) => self.nfa.is_match(haystack),
+        }
+    }
+}
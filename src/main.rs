@@ -1,62 +1,625 @@
+mod builder;
+mod charclass;
 mod helper;
 mod nfa;
+mod ops;
+mod pikevm;
 mod dfa;
+mod lazy;
 mod testing;
 
+use std::collections::BTreeMap;
 use std::fs;
 use serde_json::from_str;
-use helper::parser;
-use testing::{RegexTestSuite, SingleTest};
+use builder::{BuildError, Builder, EquivalenceError};
+use charclass::CharClass;
+use helper::{ast, parser};
+use testing::{CaptureTest, ComplementTest, EquivalenceTest, FindAllTest, ProductString, ProductTest, RegexTestSuite, SearchTest, SingleTest, SubsetTest, UniversalTest};
+
+// Counts of the checks run so far and of those that failed
+#[derive(Default)]
+struct Tally {
+    total: i32,
+    failures: i32,
+}
 
 fn main() {
     let file: String = fs::read_to_string("regex_tests.json")
         .expect("Unable to read file");
     let tests: Vec<RegexTestSuite> = from_str(&file)
         .expect("JSON was not well-formatted");
+    if let Some(name) = testing::duplicate_name(&tests) {
+        panic!("Test suite name {} is used more than once", name);
+    }
 
-    let mut total: i32 = 0;
-    let mut failures: i32 = 0;
+    let mut tally: Tally = Tally::default();
 
     for test in tests {
         println!("\n=== Test suite {}: `{}` ===", test.name, test.regex);
 
-        // build the DFA once per suite
-        let tokens: Vec<parser::Token>  = parser::tokenize(&test.regex);
-        let tokens: Vec<parser::Token> = parser::to_postfix(tokens);
-        let nfa: nfa::NFA = parser::build_nfa(tokens);
-        let dfa: dfa::DFA = nfa.to_dfa();
+        let builder: Builder = suite_builder(&test);
+        let Some((nfa, dfa)) = build(&test, &builder, &mut tally) else {
+            println!();
+            continue;
+        };
+        let minimized: dfa::DFA = dfa.minimize();
 
         if test.visualize {
             nfa.visualize();
             dfa.visualize();
         }
 
-        for SingleTest { input, expected } in &test.test_strings {
-            total += 1;
-            let result = dfa.accepts_word(input);
-            if result != *expected {
-                failures += 1;
+        check_sizes(&test, &dfa, &minimized, &mut tally);
+        check_test_strings(&test, &dfa, &minimized, &mut tally);
+        check_searches(&test, &nfa, &dfa, &minimized, &mut tally);
+        check_find_all(&test, &dfa, &mut tally);
+        check_captures(&test, &nfa, &mut tally);
+        check_complement(&test, &dfa, &mut tally);
+        check_product(&test, &builder, &dfa, &mut tally);
+        check_equivalence(&test, &builder, &dfa, &mut tally);
+        check_subsets(&test, &builder, &dfa, &mut tally);
+        check_language(&test, &dfa, &minimized, &mut tally);
+        check_words(&test, &dfa, &minimized, &mut tally);
+        println!();
+    }
+
+    println!("\nRan {} tests: {} passed, {} failed\n",
+             tally.total, tally.total - tally.failures, tally.failures);
+
+    if tally.failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+// The `Builder` with the limits the suite asks for
+fn suite_builder(test: &RegexTestSuite) -> Builder {
+    let mut builder: Builder = Builder::new();
+    if let Some(limit) = test.repeat_limit {
+        builder = builder.repeat_limit(limit);
+    }
+    if let Some(limit) = test.state_limit {
+        builder = builder.state_limit(limit);
+    }
+    if let Some(ranges) = &test.complete_over {
+        builder = builder.complete_over(CharClass::new(ranges.clone()));
+    }
+    builder
+}
+
+// Builds the suite's NFA and DFA once. A suite that expects either step to
+// fail is checked here and has nothing left to run.
+fn build(test: &RegexTestSuite, builder: &Builder, tally: &mut Tally) -> Option<(nfa::NFA, dfa::DFA)> {
+    let nfa: nfa::NFA = match (builder.build_nfa(&test.regex), test.error_at) {
+        (Ok(nfa), None) => nfa,
+        (Err(err), Some(offset))
+            if err.offset == offset && test.error_kind.as_ref().is_none_or(|kind| *kind == format!("{:?}", err.kind)) =>
+        {
+            tally.total += 1;
+            println!("  ✅ [PASS] Rejected with: {}", err);
+            return None;
+        }
+        (Err(err), _) => {
+            tally.total += 1;
+            tally.failures += 1;
+            println!("  ❌ [FAIL] {}\n{}", err, err.caret(&test.regex));
+            return None;
+        }
+        (Ok(_), Some(offset)) => {
+            tally.total += 1;
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Expected a syntax error at offset {}", offset);
+            return None;
+        }
+    };
+    let dfa: dfa::DFA = match (builder.build_dfa(&test.regex), test.states_reached) {
+        (Ok(dfa), None) => dfa,
+        (Err(BuildError::TooManyStates(err)), Some(states)) if err.states == states => {
+            tally.total += 1;
+            println!("  ✅ [PASS] Rejected with: {}", err);
+            return None;
+        }
+        (Err(err), _) => {
+            tally.total += 1;
+            tally.failures += 1;
+            println!("  ❌ [FAIL] {}", err);
+            return None;
+        }
+        (Ok(dfa), Some(states)) => {
+            tally.total += 1;
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Expected the DFA to give up at {} states, got {}", states, dfa.state_count());
+            return None;
+        }
+    };
+    Some((nfa, dfa))
+}
+
+// Builds the DFA of a regex a check compares the suite's against, counting
+// a failed check when it can't be built
+fn build_other(builder: &Builder, check: &str, regex: &str, tally: &mut Tally) -> Option<dfa::DFA> {
+    match builder.build_dfa(regex) {
+        Ok(other) => Some(other),
+        Err(err) => {
+            tally.total += 1;
+            tally.failures += 1;
+            println!("  ❌ [FAIL] {}: {:?} failed to build: {}", check, regex, err);
+            None
+        }
+    }
+}
+
+fn check_sizes(test: &RegexTestSuite, dfa: &dfa::DFA, minimized: &dfa::DFA, tally: &mut Tally) {
+    if let Some(expected) = test.classes {
+        tally.total += 1;
+        let classes: usize = dfa.alphabet.class_count();
+        if classes != expected {
+            tally.failures += 1;
+            println!("  ❌ [FAIL] DFA alphabet has {} classes instead of {}", classes, expected);
+        } else {
+            println!("  ✅ [PASS] DFA alphabet has {} classes", classes);
+        }
+    }
+
+    if let Some(expected) = test.states {
+        tally.total += 1;
+        let states: usize = minimized.state_count() - 1;
+        if states != expected {
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Minimized DFA has {} states besides DEAD instead of {}", states, expected);
+        } else {
+            println!("  ✅ [PASS] Minimized DFA has {} states besides DEAD", states);
+        }
+    }
+
+    if let Some(expected) = test.min_states {
+        tally.total += 1;
+        let live: usize = minimized.live_states().iter().filter(|&&live| live).count();
+        if live != expected {
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Minimized DFA has {} live states instead of {}", live, expected);
+        } else {
+            println!("  ✅ [PASS] Minimized DFA has {} live states", live);
+        }
+    }
+}
+
+fn check_test_strings(test: &RegexTestSuite, dfa: &dfa::DFA, minimized: &dfa::DFA, tally: &mut Tally) {
+    // The shunting-yard pipeline is kept for compatibility and has to agree with the parser
+    let postfix_dfa: Option<dfa::DFA> = parser::tokenize(&test.regex)
+        .and_then(parser::to_postfix)
+        .and_then(ast::from_postfix)
+        .and_then(|ast| parser::build_nfa(&ast))
+        .ok()
+        .map(|nfa| nfa.to_dfa());
+
+    for SingleTest { input, expected } in &test.test_strings {
+        tally.total += 1;
+        let result = dfa.accepts_word(input);
+
+        // Every other way of building the automaton has to give the same answer
+        let mut disagreements: Vec<String> = Vec::new();
+        let postfix_result = postfix_dfa.as_ref().map(|dfa| dfa.accepts_word(input));
+        if postfix_result != Some(result) {
+            disagreements.push(format!("postfix pipeline got {:?}", postfix_result));
+        }
+        if minimized.accepts_word(input) != result {
+            disagreements.push(format!("minimized DFA got {}", !result));
+        }
+
+        if !disagreements.is_empty() {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Input: {:<10} | DFA got {} but {}",
+                format!("{:?}", input),
+                result,
+                disagreements.join(", ")
+            );
+        } else if result != *expected {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Input: {:<10} | Expected: {:<5} | Got: {}",
+                format!("{:?}", input),
+                expected,
+                result
+            );
+        } else {
+            println!(
+                "  ✅ [PASS] Input: {:<10} | Result matches expected: {}",
+                format!("{:?}", input),
+                result
+            );
+        }
+    }
+}
+
+fn check_searches(test: &RegexTestSuite, nfa: &nfa::NFA, dfa: &dfa::DFA, minimized: &dfa::DFA, tally: &mut Tally) {
+    let mut lazy: lazy::LazyDFA = nfa.to_lazy_dfa();
+    let mut starved: lazy::LazyDFA = lazy::LazyDFA::new(nfa, 0);
+    for SearchTest { input, expected } in &test.search_strings {
+        tally.total += 1;
+        let result: Option<(usize, usize)> = dfa.find(input).map(|m| (m.start, m.end));
+        let minimized_result: Option<(usize, usize)> = minimized.find(input).map(|m| (m.start, m.end));
+
+        let nfa_result: Option<(usize, usize)> = nfa.find(input).map(|m| (m.start, m.end));
+        let lazy_result: Option<(usize, usize)> = lazy.find(input).map(|m| (m.start, m.end));
+        // A cache too small for a single state keeps falling back to the NFA
+        let fallback_result: Option<(usize, usize)> = starved.find(input).map(|m| (m.start, m.end));
+        let is_match: bool = expected.is_some();
+        let is_match_results: [bool; 4] = [dfa.is_match(input), nfa.is_match(input), lazy.is_match(input), starved.is_match(input)];
+
+        if result != *expected
+            || minimized_result != result
+            || nfa_result != result
+            || lazy_result != result
+            || fallback_result != result
+            || is_match_results.iter().any(|&found| found != is_match)
+        {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Search: {:<10} | Expected: {:?} | Got: {:?} (minimized: {:?}, NFA: {:?}, lazy: {:?} / {:?}, is_match: {:?})",
+                format!("{:?}", input),
+                expected,
+                result,
+                minimized_result,
+                nfa_result,
+                lazy_result,
+                fallback_result,
+                is_match_results
+            );
+        } else {
+            println!(
+                "  ✅ [PASS] Search: {:<10} | Found: {:?}",
+                format!("{:?}", input),
+                result
+            );
+        }
+    }
+}
+
+fn check_find_all(test: &RegexTestSuite, dfa: &dfa::DFA, tally: &mut Tally) {
+    for FindAllTest { input, expected } in &test.find_all {
+        tally.total += 1;
+        let result: Vec<(usize, usize)> = dfa.find_iter(input).map(|m| (m.start, m.end)).collect();
+
+        if result != *expected {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Find all: {:<10} | Expected: {:?} | Got: {:?}",
+                format!("{:?}", input),
+                expected,
+                result
+            );
+        } else {
+            println!(
+                "  ✅ [PASS] Find all: {:<10} | Found: {:?}",
+                format!("{:?}", input),
+                result
+            );
+        }
+    }
+}
+
+fn check_captures(test: &RegexTestSuite, nfa: &nfa::NFA, tally: &mut Tally) {
+    for CaptureTest { input, expected, named } in &test.captures {
+        tally.total += 1;
+        let caps = nfa.captures(input);
+        let result: Option<Vec<Option<(usize, usize)>>> = caps.as_ref().map(|caps| {
+            (0..caps.group_count())
+                .map(|i| caps.get(i).map(|m| (m.start, m.end)))
+                .collect()
+        });
+        let named_result: BTreeMap<String, Option<(usize, usize)>> = named
+            .keys()
+            .map(|name| {
+                let span = caps.as_ref().and_then(|caps| caps.name(name)).map(|m| (m.start, m.end));
+                (name.clone(), span)
+            })
+            .collect();
+
+        if named_result != *named {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Captures: {:<10} | Expected named groups: {:?} | Got: {:?}",
+                format!("{:?}", input),
+                named,
+                named_result
+            );
+        } else if result != *expected {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Captures: {:<10} | Expected: {:?} | Got: {:?}",
+                format!("{:?}", input),
+                expected,
+                result
+            );
+        } else {
+            println!(
+                "  ✅ [PASS] Captures: {:<10} | Groups: {:?}",
+                format!("{:?}", input),
+                result
+            );
+        }
+    }
+}
+
+fn check_complement(test: &RegexTestSuite, dfa: &dfa::DFA, tally: &mut Tally) {
+    if let Some(ComplementTest { alphabet, test_strings }) = &test.complement {
+        let complement: dfa::DFA = dfa.complement(&CharClass::new(alphabet.clone()));
+        let minimized_complement: dfa::DFA = complement.minimize();
+
+        for SingleTest { input, expected } in test_strings {
+            tally.total += 1;
+            let result: bool = complement.accepts_word(input);
+
+            if result != *expected || minimized_complement.accepts_word(input) != result {
+                tally.failures += 1;
                 println!(
-                    "  ❌ [FAIL] Input: {:<10} | Expected: {:<5} | Got: {}",
-                    format!("\"{}\"", input),
+                    "  ❌ [FAIL] Complement: {:<10} | Expected: {:<5} | Got: {} (minimized: {})",
+                    format!("{:?}", input),
                     expected,
-                    result
+                    result,
+                    minimized_complement.accepts_word(input)
                 );
             } else {
                 println!(
-                    "  ✅ [PASS] Input: {:<10} | Result matches expected: {}",
-                    format!("\"{}\"", input),
+                    "  ✅ [PASS] Complement: {:<10} | Result matches expected: {}",
+                    format!("{:?}", input),
                     result
                 );
             }
         }
-        println!();
     }
+}
 
-    println!("\nRan {} tests: {} passed, {} failed\n",
-             total, total - failures, failures);
+fn check_product(test: &RegexTestSuite, builder: &Builder, dfa: &dfa::DFA, tally: &mut Tally) {
+    if let Some(ProductTest { regex, test_strings }) = &test.product {
+        let Some(other) = build_other(builder, "Product", regex, tally) else {
+            return;
+        };
+        let operations: Vec<(&str, dfa::DFA, dfa::DFA)> = [
+            ("intersect", dfa.intersect(&other)),
+            ("union", dfa.union(&other)),
+            ("difference", dfa.difference(&other)),
+        ]
+        .into_iter()
+        .map(|(operation, product)| {
+            let minimized_product: dfa::DFA = product.minimize();
+            (operation, product, minimized_product)
+        })
+        .collect();
 
-    if failures > 0 {
-        std::process::exit(1);
+        for ProductString { input, intersect, union, difference } in test_strings {
+            for ((operation, product, minimized_product), expected) in operations.iter().zip([intersect, union, difference]) {
+                tally.total += 1;
+                let result: bool = product.accepts_word(input);
+                let minimized_result: bool = minimized_product.accepts_word(input);
+
+                if result != *expected || minimized_result != result {
+                    tally.failures += 1;
+                    println!(
+                        "  ❌ [FAIL] {:<10} {:<10} | Expected: {:<5} | Got: {} (minimized: {})",
+                        operation,
+                        format!("{:?}", input),
+                        expected,
+                        result,
+                        minimized_result
+                    );
+                } else {
+                    println!(
+                        "  ✅ [PASS] {:<10} {:<10} | Result matches expected: {}",
+                        operation,
+                        format!("{:?}", input),
+                        result
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn check_equivalence(test: &RegexTestSuite, builder: &Builder, dfa: &dfa::DFA, tally: &mut Tally) {
+    for EquivalenceTest { regex, counterexample } in test.equivalent_to.iter().flatten() {
+        tally.total += 1;
+        // The counterexample must not depend on which side it's asked from
+        let result: Result<(), EquivalenceError> = builder.equivalent(&test.regex, regex);
+        let flipped: Result<(), EquivalenceError> = builder.equivalent(regex, &test.regex);
+        let (result, flipped) = match (result, flipped) {
+            (Err(EquivalenceError::Build(err)), _) | (_, Err(EquivalenceError::Build(err))) => {
+                tally.failures += 1;
+                println!("  ❌ [FAIL] Equivalent: {:?} failed to build: {}", regex, err);
+                continue;
+            }
+            (Err(EquivalenceError::Counterexample(c)), Err(EquivalenceError::Counterexample(f))) => (Some(c), Some(f)),
+            (Err(EquivalenceError::Counterexample(c)), Ok(())) => (Some(c), None),
+            (Ok(()), Err(EquivalenceError::Counterexample(f))) => (None, Some(f)),
+            (Ok(()), Ok(())) => (None, None),
+        };
+        let found: Option<&str> = result.as_ref().map(|c| c.word.as_str());
+        let consistent: bool = match (&result, &flipped) {
+            (None, None) => true,
+            (Some(c), Some(f)) => c.word == f.word
+                && c.accepted_by_first == dfa.accepts_word(&c.word)
+                && f.accepted_by_first != c.accepted_by_first,
+            _ => false,
+        };
+
+        if found != counterexample.as_deref() || !consistent {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Equivalent: {:<10} | Expected counterexample: {:?} | Got: {:?} (flipped: {:?})",
+                regex,
+                counterexample,
+                result,
+                flipped
+            );
+        } else {
+            match &result {
+                None => println!("  ✅ [PASS] Equivalent: {:<10} | Same language", regex),
+                Some(c) => println!("  ✅ [PASS] Equivalent: {:<10} | {}", regex, c),
+            }
+        }
+    }
+}
+
+fn check_subsets(test: &RegexTestSuite, builder: &Builder, dfa: &dfa::DFA, tally: &mut Tally) {
+    for SubsetTest { regex, witness } in test.subset_of.iter().flatten() {
+        let Some(other) = build_other(builder, "Subset", regex, tally) else {
+            continue;
+        };
+        tally.total += 1;
+        let result: Result<(), ops::Counterexample> = dfa.is_subset(&other);
+        let found: Option<&str> = result.as_ref().err().map(|c| c.word.as_str());
+        let valid: bool = match &result {
+            Ok(()) => true,
+            Err(c) => c.accepted_by_first && dfa.accepts_word(&c.word) && !other.accepts_word(&c.word),
+        };
+
+        if found != witness.as_deref() || !valid {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Subset: {:<10} | Expected witness: {:?} | Got: {:?}",
+                regex,
+                witness,
+                result
+            );
+        } else {
+            match found {
+                None => println!("  ✅ [PASS] Subset: {:<10} | Every word is accepted by it", regex),
+                Some(word) => println!("  ✅ [PASS] Subset: {:<10} | Witness: {:?}", regex, word),
+            }
+        }
+    }
+}
+
+fn check_language(test: &RegexTestSuite, dfa: &dfa::DFA, minimized: &dfa::DFA, tally: &mut Tally) {
+    if let Some(expected) = test.empty {
+        tally.total += 1;
+        let result: bool = dfa.is_empty();
+        if result != expected || minimized.is_empty() != result {
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Empty: Expected: {} | Got: {} (minimized: {})", expected, result, minimized.is_empty());
+        } else {
+            println!("  ✅ [PASS] Empty: {}", result);
+        }
+    }
+
+    if let Some(expected) = test.finite {
+        tally.total += 1;
+        let result: bool = dfa.is_finite();
+        // An infinite language has no longest word
+        if result != expected || minimized.is_finite() != result || (!result && dfa.max_length().is_some()) {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Finite: Expected: {} | Got: {} (minimized: {}, max length: {:?})",
+                expected,
+                result,
+                minimized.is_finite(),
+                dfa.max_length()
+            );
+        } else {
+            println!("  ✅ [PASS] Finite: {}", result);
+        }
+    }
+
+    if let Some(expected) = test.max_length {
+        tally.total += 1;
+        let result: Option<usize> = dfa.max_length();
+        if result != Some(expected) || minimized.max_length() != result {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Max length: Expected: {} | Got: {:?} (minimized: {:?})",
+                expected,
+                result,
+                minimized.max_length()
+            );
+        } else {
+            println!("  ✅ [PASS] Max length: {}", expected);
+        }
+    }
+
+    if let Some(UniversalTest { alphabet, expected }) = &test.universal {
+        tally.total += 1;
+        let alphabet: CharClass = CharClass::new(alphabet.clone());
+        let result: bool = dfa.is_universal(&alphabet);
+        if result != *expected || minimized.is_universal(&alphabet) != result {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Universal: Expected: {} | Got: {} (minimized: {})",
+                expected,
+                result,
+                minimized.is_universal(&alphabet)
+            );
+        } else {
+            println!("  ✅ [PASS] Universal: {}", result);
+        }
+    }
+}
+
+fn check_words(test: &RegexTestSuite, dfa: &dfa::DFA, minimized: &dfa::DFA, tally: &mut Tally) {
+    if let Some(expected) = &test.words {
+        tally.total += 1;
+        let result: Vec<String> = dfa.words_by_length().take(expected.len()).collect();
+        let minimized_result: Vec<String> = minimized.words_by_length().take(expected.len()).collect();
+        let shortest: Option<String> = dfa.shortest_match();
+
+        if result != *expected
+            || minimized_result != result
+            || shortest.as_ref() != expected.first()
+            || !result.iter().all(|word| dfa.accepts_word(word))
+        {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Words: Expected: {:?} | Got: {:?} (minimized: {:?}, shortest: {:?})",
+                expected,
+                result,
+                minimized_result,
+                shortest
+            );
+        } else {
+            println!("  ✅ [PASS] Words: {:?}", result);
+        }
+    }
+
+    if let Some(expected) = &test.word_counts {
+        tally.total += 1;
+        let expected: Vec<Option<u128>> = expected
+            .iter()
+            .map(|count| count.as_ref().map(|count| count.parse().expect("word counts must be integers")))
+            .collect();
+        // The list starts with the count of the empty word, so it can't be empty
+        let Some(max_length) = expected.len().checked_sub(1) else {
+            tally.failures += 1;
+            println!("  ❌ [FAIL] Word counts: Expected a count for at least the empty word");
+            return;
+        };
+        let counts: ops::WordCounts = dfa.word_counts(max_length);
+        let minimized_counts: ops::WordCounts = minimized.word_counts(max_length);
+        let expected_total: Option<u128> = expected.iter().try_fold(0u128, |total, &count| total.checked_add(count?));
+
+        if counts.coefficients != expected
+            || minimized_counts != counts
+            || (0..=max_length).any(|length| dfa.count_words(length) != expected[length])
+            || counts.total() != expected_total
+        {
+            tally.failures += 1;
+            println!(
+                "  ❌ [FAIL] Word counts: Expected: {:?} | Got: {:?} (minimized: {:?})",
+                expected,
+                counts.coefficients,
+                minimized_counts.coefficients
+            );
+        } else {
+            println!("  ✅ [PASS] Word counts: {}", counts);
+        }
+
+        if let Some(function) = &test.generating_function {
+            tally.total += 1;
+            if counts.to_string() != *function {
+                tally.failures += 1;
+                println!("  ❌ [FAIL] Generating function: Expected: {} | Got: {}", function, counts);
+            } else {
+                println!("  ✅ [PASS] Generating function: {}", function);
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,69 +1,186 @@
-use std::collections::HashMap;
+use std::fmt;
+use crate::charclass::CharClass;
 
+// The label of an NFA transition: either an ε-move or any character of a class.
+// `Save(slot)` is an ε-move that also records the current position in a capture
+// slot: group i starts at slot 2i and ends at slot 2i + 1. `Look(look)` is an
+// ε-move that can only be taken where the assertion holds.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Epsilon,
+    Save(usize),
+    Look(Look),
+    Class(CharClass),
+}
+
+impl Symbol {
+    // Whether the transition can always be taken without reading a character
+    pub fn is_epsilon(&self) -> bool {
+        matches!(self, Symbol::Epsilon | Symbol::Save(_))
+    }
+}
+
+// Zero-width assertions about the characters around a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Look {
+    Start,           // ^
+    End,             // $
+    WordBoundary,    // \b
+    NotWordBoundary, // \B
+}
+
+// Whether `c` is matched by `\w`
+pub fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Look {
+    // `prev` and `next` tell whether the characters before and after the
+    // position are word characters, or are `None` at the edges of the haystack.
+    // The edges count as non-word characters for `\b` and `\B`.
+    pub fn holds(self, prev: Option<bool>, next: Option<bool>) -> bool {
+        match self {
+            Look::Start => prev.is_none(),
+            Look::End => next.is_none(),
+            Look::WordBoundary => prev.unwrap_or(false) != next.unwrap_or(false),
+            Look::NotWordBoundary => prev.unwrap_or(false) == next.unwrap_or(false),
+        }
+    }
+
+    // Whether the assertion holds at byte offset `pos` of `haystack`
+    pub fn holds_at(self, haystack: &str, pos: usize) -> bool {
+        let prev = haystack[..pos].chars().next_back().map(is_word_char);
+        let next = haystack[pos..].chars().next().map(is_word_char);
+        self.holds(prev, next)
+    }
+}
+
+impl fmt::Display for Look {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Look::Start => write!(f, "^"),
+            Look::End => write!(f, "$"),
+            Look::WordBoundary => write!(f, "\\b"),
+            Look::NotWordBoundary => write!(f, "\\B"),
+        }
+    }
+}
+
+// States are numbered 0..transitions.len(), so `transitions[q]` lists the
+// outgoing edges of state q. Edges keep the order they were added in.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub struct NFA {
-    pub(crate) sigma: Vec<char>,
-    pub(crate) states: Vec<String>,
-    pub(crate) q0: String,
-    pub(crate) final_states: Vec<String>,
-    pub(crate) transitions: HashMap<String, HashMap<char, Vec<String>>>
+    pub(crate) sigma: Vec<CharClass>,
+    pub(crate) q0: usize,
+    pub(crate) final_states: Vec<usize>,
+    pub(crate) transitions: Vec<Vec<(Symbol, usize)>>,
+    // Name of every capture group by index, filled in by `parser::build_nfa`,
+    // which also makes its length the number of groups
+    pub(crate) group_names: Vec<Option<String>>,
 }
 
 impl NFA {
-    pub fn new(sigma: Vec<char>, states: Vec<String>, q0: String, final_states: Vec<String>, transitions: HashMap<String, HashMap<char, Vec<String>>>) -> NFA {
+    pub fn new(sigma: Vec<CharClass>, q0: usize, final_states: Vec<usize>, transitions: Vec<Vec<(Symbol, usize)>>) -> NFA {
         NFA {
             sigma,
-            states,
             q0,
             final_states,
-            transitions
+            transitions,
+            group_names: Vec::new(),
         }
     }
 
+    fn from_symbol(symbol: Symbol) -> NFA {
+        let sigma: Vec<CharClass> = match &symbol {
+            Symbol::Class(class) => vec![class.clone()],
+            Symbol::Epsilon | Symbol::Save(_) | Symbol::Look(_) => vec![],
+        };
+        NFA::new(sigma, 0, vec![1], vec![vec![(symbol, 1)], vec![]])
+    }
+
     pub fn from_char(c: char) -> NFA {
-        let mut transitions = HashMap::new();
-        transitions.insert("q0".to_string(), HashMap::new());
-        transitions.get_mut("q0").unwrap().insert(c, vec!["q1".to_string()]);
-        NFA::new(vec![c], vec!["q0".to_string(), "q1".to_string()], "q0".to_string(), vec!["q1".to_string()], transitions)
+        NFA::from_symbol(Symbol::Class(CharClass::from_char(c)))
     }
 
-    // Renames the states of the NFA to q{start_index}, q{start_index + 1}, ... and so on
-    // This is useful when we want to combine multiple NFAs into one
-    pub fn rename_states(self, start_index: usize) -> NFA {
-        let mut mapping: HashMap<String, String> = HashMap::new();
-        let mut new_idx: usize = start_index;
+    // A single transition on any character of the class, so `[a-z]` doesn't
+    // have to be spelled out as a union of 26 single-character NFAs
+    pub fn from_class(class: CharClass) -> NFA {
+        NFA::from_symbol(Symbol::Class(class))
+    }
 
-        for state in &self.states {
-            mapping.insert(state.clone(), format!("q{}", new_idx));
-            new_idx += 1;
-        }
+    // Accepts only the empty word
+    pub fn epsilon() -> NFA {
+        NFA::from_symbol(Symbol::Epsilon)
+    }
 
-        let mut new_transitions: HashMap<String, HashMap<char, Vec<String>>> = HashMap::new();
-        for (old_from, map) in self.transitions.into_iter() {
-            let new_from: String = mapping[&old_from].clone();
-            let entry: &mut HashMap<char, Vec<String>> = new_transitions.entry(new_from.clone()).or_insert_with(HashMap::new);
+    // Accepts only the empty word, recording the position it was crossed at in `slot`
+    pub fn save(slot: usize) -> NFA {
+        NFA::from_symbol(Symbol::Save(slot))
+    }
 
-            for (symbol, to_list) in map {
-                let targets: Vec<String> = to_list.into_iter().map(|old_to| mapping[&old_to].clone()).collect::<Vec<_>>();
+    // Accepts only the empty word, and only where `look` holds
+    pub fn look(look: Look) -> NFA {
+        NFA::from_symbol(Symbol::Look(look))
+    }
 
-                entry.entry(symbol).or_insert_with(Vec::new).extend(targets);
-            }
-        }
+    // Whether any transition of the NFA asserts `look`
+    pub fn uses_look(&self, look: Look) -> bool {
+        self.transitions.iter().flatten().any(|(symbol, _)| *symbol == Symbol::Look(look))
+    }
+
+    // Number of capture groups, counting the implicit group 0 around the whole
+    // match. This is how many groups the parser opened, including those under
+    // `{0}` that leave no `Save` behind. An NFA not built from a regex only has group 0.
+    pub fn group_count(&self) -> usize {
+        self.group_names.len().max(1)
+    }
 
-        let new_states: Vec<String> = mapping.values().cloned().collect();
-        let new_q0: String = mapping[&self.q0].clone();
-        let new_finals: Vec<String> = self
-            .final_states
+    pub fn state_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    // Renumbers the states of the NFA to start_index, start_index + 1, ... and so on
+    // This is useful when we want to combine multiple NFAs into one
+    pub fn rename_states(self, start_index: usize) -> NFA {
+        let transitions: Vec<Vec<(Symbol, usize)>> = self
+            .transitions
             .into_iter()
-            .map(|old_f: String| mapping[&old_f].clone())
+            .map(|edges| {
+                edges.into_iter()
+                    .map(|(symbol, to)| (symbol, to + start_index))
+                    .collect()
+            })
             .collect();
 
         NFA {
             sigma: self.sigma,
-            states: new_states,
-            q0: new_q0,
-            final_states: new_finals,
-            transitions: new_transitions,
+            q0: self.q0 + start_index,
+            final_states: self.final_states.into_iter().map(|f| f + start_index).collect(),
+            transitions,
+            group_names: self.group_names,
+        }
+    }
+
+    // The NFA of the reversed words, for searches that read the haystack from
+    // right to left. Every edge is turned around, `^` and `$` trade places
+    // and capture slots are dropped.
+    pub fn reverse(&self) -> NFA {
+        let start: usize = self.state_count();
+        let mut transitions: Vec<Vec<(Symbol, usize)>> = vec![Vec::new(); self.state_count() + 1];
+        for (from, edges) in self.transitions.iter().enumerate() {
+            for (symbol, to) in edges {
+                let symbol: Symbol = match symbol {
+                    Symbol::Save(_) => Symbol::Epsilon,
+                    Symbol::Look(Look::Start) => Symbol::Look(Look::End),
+                    Symbol::Look(Look::End) => Symbol::Look(Look::Start),
+                    _ => symbol.clone(),
+                };
+                transitions[*to].push((symbol, from));
+            }
         }
+        transitions[start] = self.final_states.iter().map(|&f| (Symbol::Epsilon, f)).collect();
+
+        NFA::new(self.sigma.clone(), start, vec![self.q0], transitions)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,512 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use crate::charclass::{CharClass, next_char};
+use crate::dfa::{Alphabet, DFA, DEAD};
+
+// A word two DFAs disagree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    pub word: String,
+    // Whether the first DFA is the one accepting it
+    pub accepted_by_first: bool,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (accepting, rejecting) = if self.accepted_by_first { ("first", "second") } else { ("second", "first") };
+        write!(f, "{:?} is accepted by the {} DFA but not by the {}", self.word, accepting, rejecting)
+    }
+}
+
+impl std::error::Error for Counterexample {}
+
+impl DFA {
+    // A DFA for every word over `alphabet` this one rejects.
+    //
+    // The result is complete over `alphabet`: the old DEAD becomes an
+    // accepting trap state, and only characters outside `alphabet` lead to the
+    // new DEAD. It describes a set of whole words, so the assertions of the
+    // original regex are gone: a search may start at q0 anywhere, and a match
+    // ends wherever a final state is reached.
+    pub fn complement(&self, alphabet: &CharClass) -> DFA {
+        let (product, pairs) = self.alphabet.product(&Alphabet::new(&[alphabet]));
+        let inside: Vec<bool> = product.representatives.iter().map(|&c| alphabet.contains(c)).collect();
+
+        // Every state keeps its id, except the old DEAD which becomes the trap
+        let trap: usize = self.state_count();
+        let rename = |q: usize| if q == DEAD { trap } else { q };
+
+        let mut transitions: Vec<Vec<usize>> = vec![vec![DEAD; product.class_count()]; self.state_count() + 1];
+        let mut final_states: Vec<bool> = vec![false; self.state_count() + 1];
+        for q in (DEAD + 1)..self.state_count() {
+            final_states[q] = !self.final_states[q];
+            for (index, &(class, _)) in pairs.iter().enumerate() {
+                if inside[index] {
+                    transitions[q][index] = rename(self.transitions[q][class]);
+                }
+            }
+        }
+        final_states[trap] = true;
+        for (index, target) in transitions[trap].iter_mut().enumerate() {
+            if inside[index] {
+                *target = trap;
+            }
+        }
+
+        let q0: usize = rename(self.q0);
+        DFA {
+            matches_before: final_states.iter().map(|&f| vec![f; product.class_count()]).collect(),
+            alphabet: product,
+            complete_over: Some(alphabet.clone()),
+            q0,
+            start_states: [q0; 3],
+            final_states,
+            transitions,
+            labels: None,
+        }
+    }
+
+    // The product construction: runs both DFAs side by side, one state for
+    // every pair of states reachable together, and accepts where `accept`
+    // says so given whether each of them accepts. Like the complement, the
+    // result describes a set of whole words and keeps no assertions.
+    fn product(&self, other: &DFA, accept: impl Fn(bool, bool) -> bool) -> DFA {
+        let (alphabet, classes) = self.alphabet.product(&other.alphabet);
+
+        // A pair is dead when no future of its two halves can make it accept
+        let outcomes = |q: usize| if q == DEAD { &[false][..] } else { &[false, true][..] };
+        let is_dead = |(p, q): (usize, usize)| {
+            !outcomes(p).iter().any(|&a| outcomes(q).iter().any(|&b| accept(a, b)))
+        };
+
+        let mut ids: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut pairs: Vec<(usize, usize)> = vec![(DEAD, DEAD)];
+        let mut transitions: Vec<Vec<usize>> = vec![vec![DEAD; alphabet.class_count()]];
+
+        let start: (usize, usize) = (self.q0, other.q0);
+        let q0: usize = if is_dead(start) { DEAD } else { 1 };
+        if q0 != DEAD {
+            ids.insert(start, q0);
+            pairs.push(start);
+        }
+
+        // Pairs are numbered in the order they are found, so they are also
+        // expanded in that order
+        let mut next: usize = DEAD + 1;
+        while next < pairs.len() {
+            let (p, q) = pairs[next];
+            let row: Vec<usize> = classes
+                .iter()
+                .map(|&(a, b)| {
+                    let pair: (usize, usize) = (self.transitions[p][a], other.transitions[q][b]);
+                    if is_dead(pair) {
+                        return DEAD;
+                    }
+                    *ids.entry(pair).or_insert_with(|| {
+                        pairs.push(pair);
+                        pairs.len() - 1
+                    })
+                })
+                .collect();
+            transitions.push(row);
+            next += 1;
+        }
+
+        let final_states: Vec<bool> = pairs
+            .iter()
+            .enumerate()
+            .map(|(id, &(p, q))| id != DEAD && accept(self.final_states[p], other.final_states[q]))
+            .collect();
+
+        DFA {
+            matches_before: final_states.iter().map(|&f| vec![f; alphabet.class_count()]).collect(),
+            alphabet,
+            complete_over: None,
+            q0,
+            start_states: [q0; 3],
+            final_states,
+            transitions,
+            labels: None,
+        }
+    }
+
+    // Words both DFAs accept
+    pub fn intersect(&self, other: &DFA) -> DFA {
+        self.product(other, |a, b| a && b)
+    }
+
+    // Words either DFA accepts
+    pub fn union(&self, other: &DFA) -> DFA {
+        self.product(other, |a, b| a || b)
+    }
+
+    // Words this DFA accepts and `other` rejects
+    pub fn difference(&self, other: &DFA) -> DFA {
+        self.product(other, |a, b| a && !b)
+    }
+
+    // The first word the DFA accepts in shortlex order, i.e. the smallest of
+    // its shortest words, or None if it accepts none. A BFS from q0 that
+    // follows the smallest character of every class.
+    pub fn shortest_match(&self) -> Option<String> {
+        let mut parent: Vec<Option<(usize, char)>> = vec![None; self.state_count()];
+        let mut visited: Vec<bool> = vec![false; self.state_count()];
+        let mut queue: VecDeque<usize> = VecDeque::from([self.q0]);
+        visited[DEAD] = true;
+        visited[self.q0] = true;
+
+        // Classes are numbered in the order of their smallest character, so
+        // trying them in order finds the smallest word of each length first
+        while let Some(state) = queue.pop_front() {
+            if self.final_states[state] {
+                let mut word: Vec<char> = Vec::new();
+                let mut current: usize = state;
+                while let Some((from, c)) = parent[current] {
+                    word.push(c);
+                    current = from;
+                }
+                return Some(word.into_iter().rev().collect());
+            }
+
+            for (index, &to) in self.transitions[state].iter().enumerate() {
+                if !visited[to] {
+                    visited[to] = true;
+                    parent[to] = Some((state, self.alphabet.representatives[index]));
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Whether both DFAs accept the same words, as told by `accepts_word`.
+    // If they don't, the error holds a shortest word they disagree on, e.g.
+    //   builder.build_dfa("(a|b)*")?.equivalent(&builder.build_dfa("(a*b*)*")?)
+    // proves the rewrite kept the language.
+    pub fn equivalent(&self, other: &DFA) -> Result<(), Counterexample> {
+        match self.product(other, |a, b| a != b).shortest_match() {
+            Some(word) => Err(Counterexample { accepted_by_first: self.accepts_word(&word), word }),
+            None => Ok(()),
+        }
+    }
+
+    // Whether every word this DFA accepts is accepted by `other` as well.
+    // If not, the error holds a shortest word only this one accepts.
+    pub fn is_subset(&self, other: &DFA) -> Result<(), Counterexample> {
+        match self.difference(other).shortest_match() {
+            Some(word) => Err(Counterexample { word, accepted_by_first: true }),
+            None => Ok(()),
+        }
+    }
+
+    // States on some path from q0 to a final state. The others make no
+    // difference to which words are accepted.
+    fn useful_states(&self) -> Vec<bool> {
+        let mut reachable: Vec<bool> = vec![false; self.state_count()];
+        let mut reverse: Vec<Vec<usize>> = vec![Vec::new(); self.state_count()];
+        let mut stack: Vec<usize> = vec![self.q0];
+        reachable[self.q0] = true;
+        while let Some(state) = stack.pop() {
+            for &to in &self.transitions[state] {
+                reverse[to].push(state);
+                if !reachable[to] {
+                    reachable[to] = true;
+                    stack.push(to);
+                }
+            }
+        }
+
+        let mut useful: Vec<bool> = (0..self.state_count()).map(|q| reachable[q] && self.final_states[q]).collect();
+        let mut stack: Vec<usize> = (0..self.state_count()).filter(|&q| useful[q]).collect();
+        while let Some(state) = stack.pop() {
+            for &from in &reverse[state] {
+                if !useful[from] {
+                    useful[from] = true;
+                    stack.push(from);
+                }
+            }
+        }
+
+        useful
+    }
+
+    // The useful states, each one after every state it leads to, or None if
+    // they form a cycle, which is what makes a language infinite
+    fn useful_postorder(&self) -> Option<Vec<usize>> {
+        let useful: Vec<bool> = self.useful_states();
+        let mut visited: Vec<bool> = vec![false; self.state_count()];
+        let mut on_stack: Vec<bool> = vec![false; self.state_count()];
+        let mut order: Vec<usize> = Vec::new();
+        if !useful[self.q0] {
+            return Some(order);
+        }
+
+        // Every entry is a state and the index of the next transition to follow
+        let mut stack: Vec<(usize, usize)> = vec![(self.q0, 0)];
+        visited[self.q0] = true;
+        on_stack[self.q0] = true;
+        while let Some((state, index)) = stack.pop() {
+            if index == self.transitions[state].len() {
+                on_stack[state] = false;
+                order.push(state);
+                continue;
+            }
+            stack.push((state, index + 1));
+
+            let to: usize = self.transitions[state][index];
+            if !useful[to] {
+                continue;
+            }
+            if on_stack[to] {
+                return None;
+            }
+            if !visited[to] {
+                visited[to] = true;
+                on_stack[to] = true;
+                stack.push((to, 0));
+            }
+        }
+
+        Some(order)
+    }
+
+    // Whether the DFA accepts no word at all
+    pub fn is_empty(&self) -> bool {
+        !self.useful_states()[self.q0]
+    }
+
+    // Whether the DFA accepts every word over `alphabet`
+    pub fn is_universal(&self, alphabet: &CharClass) -> bool {
+        self.complement(alphabet).is_empty()
+    }
+
+    // Whether the DFA accepts only finitely many words
+    pub fn is_finite(&self) -> bool {
+        self.useful_postorder().is_some()
+    }
+
+    // Length in characters of the longest word the DFA accepts, or None if
+    // there is no longest word because it accepts none or infinitely many
+    pub fn max_length(&self) -> Option<usize> {
+        let order: Vec<usize> = self.useful_postorder()?;
+        if order.is_empty() {
+            return None;
+        }
+
+        // Useful states always lead to a final state, so each one has a longest
+        // word left to read, and its successors come first in `order`
+        let mut longest: Vec<Option<usize>> = vec![None; self.state_count()];
+        for &state in &order {
+            let own: Option<usize> = self.final_states[state].then_some(0);
+            longest[state] = self.transitions[state]
+                .iter()
+                .filter_map(|&to| longest[to].map(|length| length + 1))
+                .chain(own)
+                .max();
+        }
+
+        longest[self.q0]
+    }
+
+    // How many words of each length up to `max_length` the DFA accepts.
+    //
+    // Counts the paths from q0 through the useful states one length at a
+    // time, every class standing for as many words as it has characters. A
+    // count that overflows stays None from then on, which is exact: every
+    // path only adds to the counts of the states it leads to.
+    pub fn word_counts(&self, max_length: usize) -> WordCounts {
+        let useful: Vec<bool> = self.useful_states();
+        let sizes: Vec<u128> = (0..self.alphabet.class_count())
+            .map(|index| self.alphabet.class(index).char_count())
+            .collect();
+
+        let mut paths: Vec<Option<u128>> = vec![Some(0); self.state_count()];
+        paths[self.q0] = Some(1);
+        let mut coefficients: Vec<Option<u128>> = Vec::new();
+        for length in 0..=max_length {
+            let count: Option<u128> = (0..self.state_count())
+                .filter(|&q| useful[q] && self.final_states[q])
+                .try_fold(0u128, |total, q| total.checked_add(paths[q]?));
+            coefficients.push(count);
+            if length == max_length {
+                break;
+            }
+
+            let mut next: Vec<Option<u128>> = vec![Some(0); self.state_count()];
+            for q in (0..self.state_count()).filter(|&q| useful[q] && paths[q] != Some(0)) {
+                for (index, &to) in self.transitions[q].iter().enumerate() {
+                    if useful[to] {
+                        let added: Option<u128> = paths[q].and_then(|count| count.checked_mul(sizes[index]));
+                        next[to] = next[to].zip(added).and_then(|(total, added)| total.checked_add(added));
+                    }
+                }
+            }
+            paths = next;
+        }
+
+        WordCounts { coefficients }
+    }
+
+    // How many words of length `length` the DFA accepts, or None if that's
+    // more than a u128 can hold
+    pub fn count_words(&self, length: usize) -> Option<u128> {
+        self.word_counts(length).coefficients[length]
+    }
+
+    // Iterates over the words the DFA accepts in shortlex order: shorter words
+    // first, and words of the same length in the order of their characters
+    pub fn words_by_length(&self) -> Words<'_> {
+        Words {
+            dfa: self,
+            finishes: vec![self.final_states.clone()],
+            max_length: if self.is_finite() { self.max_length() } else { Some(usize::MAX) },
+            states: Vec::new(),
+            word: Vec::new(),
+        }
+    }
+}
+
+// How many words of each length from 0 up to some maximum a DFA accepts,
+// i.e. the first coefficients of the generating function of its language.
+// A count is None when it doesn't fit in a u128.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordCounts {
+    pub coefficients: Vec<Option<u128>>,
+}
+
+impl WordCounts {
+    // How many words there are of all the lengths together
+    pub fn total(&self) -> Option<u128> {
+        self.coefficients.iter().try_fold(0u128, |total, &count| total.checked_add(count?))
+    }
+}
+
+// Written as a polynomial, e.g. `1 + 26x + 676x^2`, leaving out the lengths
+// without any words
+impl fmt::Display for WordCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self.coefficients
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count != Some(0))
+            .map(|(length, &count)| {
+                let count: String = match count {
+                    Some(1) if length > 0 => String::new(),
+                    Some(count) => count.to_string(),
+                    None => format!("(>{})", u128::MAX),
+                };
+                match length {
+                    0 => count,
+                    1 => format!("{}x", count),
+                    _ => format!("{}x^{}", count, length),
+                }
+            })
+            .collect();
+
+        if terms.is_empty() {
+            write!(f, "0")
+        } else {
+            write!(f, "{}", terms.join(" + "))
+        }
+    }
+}
+
+pub struct Words<'d> {
+    dfa: &'d DFA,
+    // finishes[n][q] tells whether q reaches a final state after exactly n
+    // more characters, computed up to the length of the current word
+    finishes: Vec<Vec<bool>>,
+    // No longer word is accepted. None once the words ran out.
+    max_length: Option<usize>,
+    // The last word yielded and the states the DFA went through reading it,
+    // so states[i] is the state before word[i]. Empty before the first word.
+    states: Vec<usize>,
+    word: Vec<char>,
+}
+
+impl Words<'_> {
+    // The smallest character after `after` that takes `state` to a state
+    // that finishes with `remaining` more characters, and that state
+    fn smallest(&self, state: usize, remaining: usize, after: Option<char>) -> Option<(char, usize)> {
+        let lower: char = match after {
+            Some(c) => next_char(c)?,
+            None => '\0',
+        };
+        let sigma: &[char] = &self.dfa.alphabet.sigma;
+
+        // Whole intervals are skipped at once, so `.` costs a handful of steps
+        for (i, &start) in sigma.iter().enumerate() {
+            if sigma.get(i + 1).is_some_and(|&next| next <= lower) {
+                continue;
+            }
+            let to: usize = self.dfa.transitions[state][self.dfa.alphabet.classes[i]];
+            if self.finishes[remaining - 1][to] {
+                return Some((start.max(lower), to));
+            }
+        }
+
+        None
+    }
+
+    // Completes the word with the smallest characters that still lead to a
+    // final state at the current length
+    fn fill(&mut self, length: usize) {
+        while self.word.len() < length {
+            let state: usize = self.states[self.word.len()];
+            let (c, to) = self.smallest(state, length - self.word.len(), None)
+                .expect("states on the way to a final state lead to one");
+            self.word.push(c);
+            self.states.push(to);
+        }
+    }
+}
+
+impl Iterator for Words<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let max_length: usize = self.max_length?;
+
+        // The next word of the same length replaces the last character that
+        // can still be replaced by a larger one
+        if !self.states.is_empty() {
+            let length: usize = self.word.len();
+            for i in (0..length).rev() {
+                if let Some((c, to)) = self.smallest(self.states[i], length - i, Some(self.word[i])) {
+                    self.word.truncate(i);
+                    self.states.truncate(i + 1);
+                    self.word.push(c);
+                    self.states.push(to);
+                    self.fill(length);
+                    return Some(self.word.iter().collect());
+                }
+            }
+        }
+
+        // Otherwise the next word is the smallest one of the next length
+        // that has any
+        let mut length: usize = if self.states.is_empty() { 0 } else { self.word.len() + 1 };
+        while length <= max_length {
+            while self.finishes.len() <= length {
+                let last: &[bool] = &self.finishes[self.finishes.len() - 1];
+                let next: Vec<bool> = self.dfa.transitions
+                    .iter()
+                    .map(|targets| targets.iter().any(|&to| last[to]))
+                    .collect();
+                self.finishes.push(next);
+            }
+
+            if self.finishes[length][self.dfa.q0] {
+                self.word.clear();
+                self.states = vec![self.dfa.q0];
+                self.fill(length);
+                return Some(self.word.iter().collect());
+            }
+            length += 1;
+        }
+
+        self.max_length = None;
+        None
+    }
+}
@@ -0,0 +1,274 @@
+use std::ops::Range;
+use crate::nfa::{NFA, Symbol};
+
+// Where each capture group matched. Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures<'n> {
+    slots: Vec<Option<usize>>,
+    // Group names of the NFA the match came from, by group index
+    names: &'n [Option<String>],
+}
+
+impl Captures<'_> {
+    // Byte range of group `index`, or `None` if it didn't take part in the match
+    pub fn get(&self, index: usize) -> Option<Range<usize>> {
+        match (self.slots.get(2 * index)?, self.slots.get(2 * index + 1)?) {
+            (Some(start), Some(end)) => Some(*start..*end),
+            _ => None,
+        }
+    }
+
+    // Byte range of the group declared as `(?<name>...)` or `(?P<name>...)`
+    pub fn name(&self, name: &str) -> Option<Range<usize>> {
+        let index: usize = self.names.iter().position(|group| group.as_deref() == Some(name))?;
+        self.get(index)
+    }
+
+    // Number of groups, including group 0
+    pub fn group_count(&self) -> usize {
+        self.slots.len() / 2
+    }
+}
+
+struct Thread {
+    state: usize,
+    slots: Vec<Option<usize>>,
+}
+
+// Threads in priority order, each NFA state at most once. `seen` holds the
+// step a state was last added at, so the list can be reused without clearing it.
+struct ThreadList {
+    threads: Vec<Thread>,
+    seen: Vec<usize>,
+}
+
+impl ThreadList {
+    fn new(states: usize) -> ThreadList {
+        ThreadList { threads: Vec::new(), seen: vec![usize::MAX; states] }
+    }
+}
+
+impl NFA {
+    // Follows ε-moves from `state` depth-first in edge order, so threads end up
+    // in `list` in the order of their priority. `Save` moves record `pos`, and
+    // `Look` moves are only followed if the assertion holds at `pos`.
+    fn add_thread(&self, list: &mut ThreadList, step: usize, state: usize, slots: Vec<Option<usize>>, haystack: &str, pos: usize) {
+        let mut stack: Vec<(usize, Vec<Option<usize>>)> = vec![(state, slots)];
+
+        while let Some((state, slots)) = stack.pop() {
+            if list.seen[state] == step {
+                continue;
+            }
+            list.seen[state] = step;
+
+            // Pushed in reverse so the first edge is explored first
+            for (symbol, to) in self.transitions[state].iter().rev() {
+                match symbol {
+                    Symbol::Epsilon => stack.push((*to, slots.clone())),
+                    Symbol::Save(slot) => {
+                        let mut slots = slots.clone();
+                        slots[*slot] = Some(pos);
+                        stack.push((*to, slots));
+                    }
+                    Symbol::Look(look) if look.holds_at(haystack, pos) => stack.push((*to, slots.clone())),
+                    Symbol::Look(_) | Symbol::Class(_) => {}
+                }
+            }
+
+            list.threads.push(Thread { state, slots });
+        }
+    }
+
+    // Finds the first match in `haystack` and where each group matched, using
+    // a Pike VM over the Thompson NFA in O(len(haystack) * states) time.
+    //
+    // The semantics are leftmost-first, as in RE2 and Rust's regex crate: the
+    // match starting earliest wins, and among those the alternation branches
+    // are tried left to right while `*`, `+`, `?` and `{n,m}` are greedy. This
+    // may pick a shorter match than `DFA::find`, which is leftmost-longest:
+    // `a|ab` on "ab" captures "a". When a group matches more than once, e.g.
+    // inside a star, the last iteration is reported.
+    //
+    // Iterations that match the empty word are handled like RE2 does too, which
+    // Perl and PCRE don't always agree with: `(?:baa|(?:a+|[ab])?|c|cb?)*` on
+    // "aacdba" matches 0..3 here but 0..2 in Perl.
+    pub fn captures(&self, haystack: &str) -> Option<Captures<'_>> {
+        let slot_count: usize = 2 * self.group_count();
+        let mut current: ThreadList = ThreadList::new(self.state_count());
+        let mut next: ThreadList = ThreadList::new(self.state_count());
+        let mut matched: Option<Vec<Option<usize>>> = None;
+
+        let positions = haystack
+            .char_indices()
+            .map(|(pos, c)| (pos, Some(c)))
+            .chain(std::iter::once((haystack.len(), None)));
+
+        for (step, (pos, c)) in positions.enumerate() {
+            // A new attempt starts at every position until something matched,
+            // with lower priority than the attempts that started earlier
+            if matched.is_none() {
+                let mut slots: Vec<Option<usize>> = vec![None; slot_count];
+                slots[0] = Some(pos);
+                self.add_thread(&mut current, step, self.q0, slots, haystack, pos);
+            }
+
+            if current.threads.is_empty() {
+                break;
+            }
+
+            for thread in current.threads.drain(..) {
+                if self.final_states.contains(&thread.state) {
+                    // Everything after this thread has lower priority
+                    let mut slots = thread.slots;
+                    slots[1] = Some(pos);
+                    matched = Some(slots);
+                    break;
+                }
+
+                let Some(c) = c else { continue };
+                for (symbol, to) in &self.transitions[thread.state] {
+                    if let Symbol::Class(class) = symbol {
+                        if class.contains(c) {
+                            self.add_thread(&mut next, step + 1, *to, thread.slots.clone(), haystack, pos + c.len_utf8());
+                        }
+                    }
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        matched.map(|slots| Captures { slots, names: &self.group_names })
+    }
+}
+
+// A set of NFA states with O(1) insertion, lookup and clearing that iterates in
+// insertion order, so the thread list of the next step never needs zeroing
+struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    fn new(capacity: usize) -> SparseSet {
+        SparseSet { dense: Vec::with_capacity(capacity), sparse: vec![0; capacity] }
+    }
+
+    fn contains(&self, state: usize) -> bool {
+        let index: usize = self.sparse[state];
+        index < self.dense.len() && self.dense[index] == state
+    }
+
+    // Returns false if the state was already there
+    fn insert(&mut self, state: usize) -> bool {
+        if self.contains(state) {
+            return false;
+        }
+        self.sparse[state] = self.dense.len();
+        self.dense.push(state);
+        true
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
+
+impl NFA {
+    // Adds `state` and everything reachable from it through ε-moves to `set`,
+    // recording `start` as where the match they are part of started
+    fn add_state(&self, set: &mut SparseSet, starts: &mut [usize], state: usize, start: usize, haystack: &str, pos: usize) {
+        let mut stack: Vec<usize> = vec![state];
+
+        while let Some(state) = stack.pop() {
+            if !set.insert(state) {
+                continue;
+            }
+            starts[state] = start;
+
+            for (symbol, to) in &self.transitions[state] {
+                let crossable: bool = match symbol {
+                    Symbol::Look(look) => look.holds_at(haystack, pos),
+                    _ => symbol.is_epsilon(),
+                };
+                if crossable {
+                    stack.push(*to);
+                }
+            }
+        }
+    }
+
+    // Simulates the NFA over `haystack` with one thread per state, in
+    // O(len(haystack) * states) time and without building a DFA. Threads stay
+    // ordered by where their match started, so when two of them reach the same
+    // state the one that started first keeps it. With `earliest` the search
+    // stops at the first match it sees instead of the leftmost-longest one.
+    fn search(&self, haystack: &str, earliest: bool) -> Option<Range<usize>> {
+        let mut current: SparseSet = SparseSet::new(self.state_count());
+        let mut next: SparseSet = SparseSet::new(self.state_count());
+        let mut starts: Vec<usize> = vec![0; self.state_count()];
+        let mut next_starts: Vec<usize> = vec![0; self.state_count()];
+        let mut matched: Option<Range<usize>> = None;
+
+        let positions = haystack
+            .char_indices()
+            .map(|(pos, c)| (pos, Some(c)))
+            .chain(std::iter::once((haystack.len(), None)));
+
+        for (pos, c) in positions {
+            // Once something matched, only matches starting earlier or at the
+            // same place can still beat it
+            if matched.is_none() {
+                self.add_state(&mut current, &mut starts, self.q0, pos, haystack, pos);
+            }
+
+            if current.dense.is_empty() {
+                break;
+            }
+
+            for &state in &current.dense {
+                let start: usize = starts[state];
+                if matched.as_ref().is_some_and(|m| start > m.start) {
+                    continue;
+                }
+
+                if self.final_states.contains(&state) {
+                    if earliest {
+                        return Some(start..pos);
+                    }
+                    // Any thread still here started no later than the current match,
+                    // so this match is either further left or longer
+                    matched = Some(start..pos);
+                }
+
+                let Some(c) = c else { continue };
+                for (symbol, to) in &self.transitions[state] {
+                    if let Symbol::Class(class) = symbol {
+                        if class.contains(c) {
+                            self.add_state(&mut next, &mut next_starts, *to, start, haystack, pos + c.len_utf8());
+                        }
+                    }
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+            std::mem::swap(&mut starts, &mut next_starts);
+            next.clear();
+        }
+
+        matched
+    }
+
+    // Finds the leftmost-longest match like `DFA::find`, by simulating the NFA
+    // directly. Slower per character than a DFA, but never builds one, so it
+    // stays O(len(haystack) * states) on regexes whose DFA would blow up, such
+    // as `(a|b)*a(a|b){20}`.
+    pub fn find(&self, haystack: &str) -> Option<Range<usize>> {
+        self.search(haystack, false)
+    }
+
+    // Whether any substring of `haystack` is accepted, without building a DFA
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.search(haystack, true).is_some()
+    }
+}
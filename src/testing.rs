@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashSet};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
@@ -5,7 +6,77 @@ pub struct RegexTestSuite {
     pub name: String,
     pub regex: String,
     pub visualize: bool,
+    // Byte offset of the syntax error the regex is expected to be rejected with
+    #[serde(default)]
+    pub error_at: Option<usize>,
+    // Name of the `ParseErrorKind` it is expected to be rejected with, e.g. `InvalidRepetition`
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    // Limits passed to the `Builder`
+    #[serde(default)]
+    pub repeat_limit: Option<usize>,
+    #[serde(default)]
+    pub state_limit: Option<usize>,
+    // Character ranges to build a complete DFA over instead of a partial one
+    #[serde(default)]
+    pub complete_over: Option<Vec<(char, char)>>,
+    // Number of states the subset construction is expected to give up at because of `state_limit`
+    #[serde(default)]
+    pub states_reached: Option<usize>,
+    // Number of character classes the DFA alphabet should be split into
+    #[serde(default)]
+    pub classes: Option<usize>,
+    // Number of live states the minimized DFA should have (the dead state is not counted)
+    #[serde(default)]
+    pub min_states: Option<usize>,
+    // Number of states the minimized DFA should have besides DEAD, counting the
+    // states that can't reach a match but are kept by a complete DFA
+    #[serde(default)]
+    pub states: Option<usize>,
+    #[serde(default)]
     pub test_strings: Vec<SingleTest>,
+    #[serde(default)]
+    pub search_strings: Vec<SearchTest>,
+    #[serde(default)]
+    pub find_all: Vec<FindAllTest>,
+    #[serde(default)]
+    pub captures: Vec<CaptureTest>,
+    #[serde(default)]
+    pub complement: Option<ComplementTest>,
+    #[serde(default)]
+    pub product: Option<ProductTest>,
+    #[serde(default)]
+    pub equivalent_to: Option<Vec<EquivalenceTest>>,
+    #[serde(default)]
+    pub subset_of: Option<Vec<SubsetTest>>,
+    // Whether its DFA accepts no word at all
+    #[serde(default)]
+    pub empty: Option<bool>,
+    // Whether its DFA accepts finitely many words, and the length of the longest
+    #[serde(default)]
+    pub finite: Option<bool>,
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub universal: Option<UniversalTest>,
+    // The first words its DFA should accept in shortlex order
+    #[serde(default)]
+    pub words: Option<Vec<String>>,
+    // How many words of each length from 0 on its DFA should accept, as
+    // strings because they may not fit in a JSON number, or null when there
+    // are more than a u128 holds. Optionally with the generating function
+    // they sum up to as `WordCounts` prints it.
+    #[serde(default)]
+    pub word_counts: Option<Vec<Option<String>>>,
+    #[serde(default)]
+    pub generating_function: Option<String>,
+}
+
+// The name of the first suite that reuses the name of an earlier one, since
+// the runner's output only tells suites apart by name
+pub fn duplicate_name(suites: &[RegexTestSuite]) -> Option<&str> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    suites.iter().map(|suite| suite.name.as_str()).find(|&name| !seen.insert(name))
 }
 
 #[derive(Debug, Deserialize)]
@@ -13,3 +84,74 @@ pub struct SingleTest {
     pub input: String,
     pub expected: bool,
 }
+
+// Expected byte range of the leftmost-longest match inside `input`, or `null` if there is none
+#[derive(Debug, Deserialize)]
+pub struct SearchTest {
+    pub input: String,
+    pub expected: Option<(usize, usize)>,
+}
+
+// Expected byte ranges of every non-overlapping match inside `input`, in order
+#[derive(Debug, Deserialize)]
+pub struct FindAllTest {
+    pub input: String,
+    pub expected: Vec<(usize, usize)>,
+}
+
+// Expected span of every group of the leftmost-first match, starting with group 0,
+// with `null` for groups that didn't participate. `expected` is `null` if nothing matches.
+#[derive(Debug, Deserialize)]
+pub struct CaptureTest {
+    pub input: String,
+    pub expected: Option<Vec<Option<(usize, usize)>>>,
+    // Span of named groups looked up by name, `null` for groups that didn't participate
+    #[serde(default)]
+    pub named: BTreeMap<String, Option<(usize, usize)>>,
+}
+
+// Words the complement of the regex over `alphabet` should accept or reject
+#[derive(Debug, Deserialize)]
+pub struct ComplementTest {
+    pub alphabet: Vec<(char, char)>,
+    pub test_strings: Vec<SingleTest>,
+}
+
+// Another regex to combine with the suite's, and the words the intersection,
+// union and difference of the two should accept
+#[derive(Debug, Deserialize)]
+pub struct ProductTest {
+    pub regex: String,
+    pub test_strings: Vec<ProductString>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProductString {
+    pub input: String,
+    pub intersect: bool,
+    pub union: bool,
+    pub difference: bool,
+}
+
+// Another regex and the shortest word the two DFAs disagree on, or None if
+// they accept the same words
+#[derive(Debug, Deserialize)]
+pub struct EquivalenceTest {
+    pub regex: String,
+    pub counterexample: Option<String>,
+}
+
+// Another regex and the shortest word only the suite's DFA accepts, or None
+// if the other one accepts every word it does
+#[derive(Debug, Deserialize)]
+pub struct SubsetTest {
+    pub regex: String,
+    pub witness: Option<String>,
+}
+
+// Whether the DFA should accept every word over `alphabet`
+#[derive(Debug, Deserialize)]
+pub struct UniversalTest {
+    pub alphabet: Vec<(char, char)>,
+    pub expected: bool,
+}